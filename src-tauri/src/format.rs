@@ -0,0 +1,48 @@
+/// Formats a volume level between 0.0 and 1.0 as a whole percentage, following the user's locale on Windows.
+pub fn percent(level: f32) -> String {
+    let number = (level * 100.0).round();
+
+    #[cfg(windows)]
+    {
+        let symbol = locale::percent_symbol().unwrap_or_else(|| "%".to_string());
+
+        // See `LOCALE_IPOSITIVEPERCENT` for what the patterns mean.
+        match locale::positive_percent_pattern() {
+            Some(0) => format!("{number} {symbol}"),
+            Some(2) => format!("{symbol}{number}"),
+            Some(3) => format!("{symbol} {number}"),
+            _ => format!("{number}{symbol}"),
+        }
+    }
+
+    #[cfg(not(windows))]
+    format!("{number}%")
+}
+
+#[cfg(windows)]
+mod locale {
+    use windows::{
+        core::PCWSTR,
+        Win32::Globalization::{GetLocaleInfoEx, LOCALE_IPOSITIVEPERCENT, LOCALE_SPERCENT},
+    };
+
+    pub fn positive_percent_pattern() -> Option<u32> {
+        user_locale_info(LOCALE_IPOSITIVEPERCENT)?.parse().ok()
+    }
+
+    pub fn percent_symbol() -> Option<String> {
+        user_locale_info(LOCALE_SPERCENT)
+    }
+
+    fn user_locale_info(lctype: u32) -> Option<String> {
+        // Both values are at most a few characters long.
+        let mut buffer = [0u16; 16];
+
+        // SAFETY: A null locale name refers to the user's default locale, and `buffer` is a valid slice.
+        let len = unsafe { GetLocaleInfoEx(PCWSTR::null(), lctype, Some(&mut buffer)) };
+
+        // The returned length includes the null terminator, and is 0 on failure.
+        let len = usize::try_from(len).ok()?.checked_sub(1)?;
+        Some(String::from_utf16_lossy(&buffer[..len]))
+    }
+}
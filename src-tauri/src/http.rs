@@ -0,0 +1,249 @@
+use std::{io, sync::mpsc};
+
+use serde::Deserialize;
+use serde_json::json;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt as _, AsyncReadExt as _, AsyncWriteExt as _, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+use volume_sync_core::AudioThreadCommand;
+
+use crate::read_volume_state;
+
+/// Only reachable from this machine, since there's no authentication.
+const ADDRESS: &str = "127.0.0.1:48541";
+
+/// Requests only ever carry a tiny JSON body, so anything larger is rejected outright.
+const MAX_BODY_SIZE: usize = 1024;
+
+/// The request line and headers are bounded too, so a client can't make us buffer an endless line.
+const MAX_LINE_LENGTH: usize = 8 * 1024;
+const MAX_HEADER_COUNT: usize = 32;
+
+#[derive(Debug, Deserialize)]
+struct SetVolumeRequest {
+    level: f32,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    host: Option<String>,
+    origin: Option<String>,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+/// Serves `GET /volume` and `POST /volume {"level": <level>}` on [`ADDRESS`], one request per connection.
+/// Both reply with the state of the device, after the new level was applied for `POST`.
+pub async fn serve(command_sender: mpsc::Sender<AudioThreadCommand>) {
+    let listener = match TcpListener::bind(ADDRESS).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("failed to bind HTTP server to `{ADDRESS}`: {e}");
+            return;
+        }
+    };
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                eprintln!("failed to accept HTTP connection: {e}");
+                continue;
+            }
+        };
+
+        tauri::async_runtime::spawn(handle_connection(stream, command_sender.clone()));
+    }
+}
+
+async fn handle_connection(stream: TcpStream, command_sender: mpsc::Sender<AudioThreadCommand>) {
+    let (reader, mut writer) = stream.into_split();
+
+    let (status, body) = match read_request(&mut BufReader::new(reader)).await {
+        Ok(request) => handle_request(&request, &command_sender).await,
+        Err(e) => ("400 Bad Request", json!({ "error": e.to_string() })),
+    };
+
+    let body = body.to_string();
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len(),
+    );
+
+    if let Err(e) = writer.write_all(response.as_bytes()).await {
+        eprintln!("failed to write HTTP response: {e}");
+    }
+}
+
+async fn read_request<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Request> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    let request_line = read_line(reader).await?;
+
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(invalid("malformed request line"));
+    };
+
+    let mut content_length = 0;
+    let mut host = None;
+    let mut origin = None;
+    let mut content_type = None;
+    let mut header_count = 0;
+
+    loop {
+        let header = read_line(reader).await?;
+        let header = header.trim_end();
+
+        if header.is_empty() {
+            break;
+        }
+
+        header_count += 1;
+
+        if header_count > MAX_HEADER_COUNT {
+            return Err(invalid("too many headers"));
+        }
+
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+
+        let value = value.trim();
+
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => {
+                content_length = value
+                    .parse()
+                    .map_err(|_| invalid("invalid `Content-Length`"))?;
+            }
+            "host" => host = Some(value.to_string()),
+            "origin" => origin = Some(value.to_string()),
+            "content-type" => content_type = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    if content_length > MAX_BODY_SIZE {
+        return Err(invalid("request body is too large"));
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+
+    Ok(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        host,
+        origin,
+        content_type,
+        body,
+    })
+}
+
+/// Reads a line of at most [`MAX_LINE_LENGTH`] bytes, including the line break.
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    (&mut *reader)
+        .take(MAX_LINE_LENGTH as u64)
+        .read_line(&mut line)
+        .await?;
+
+    // Either the line is too long, or the connection was closed in the middle of it.
+    if !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "request line or header is too long or incomplete",
+        ));
+    }
+
+    Ok(line)
+}
+
+async fn handle_request(
+    request: &Request,
+    command_sender: &mpsc::Sender<AudioThreadCommand>,
+) -> (&'static str, serde_json::Value) {
+    // Browsers send `Origin` with every cross-origin request that could change something, even with `no-cors`,
+    // while scripts and home automation don't. Without this, any page the user visits could set the volume.
+    if request.origin.is_some() {
+        return (
+            "403 Forbidden",
+            json!({ "error": "requests from web pages aren't allowed" }),
+        );
+    }
+
+    // A page can make its own domain resolve to 127.0.0.1 (DNS rebinding), but it can't pick the `Host` it sends.
+    if !request.host.as_deref().is_some_and(is_local_host) {
+        return (
+            "403 Forbidden",
+            json!({ "error": "`Host` has to be `127.0.0.1` or `localhost`" }),
+        );
+    }
+
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/volume") => volume_response(command_sender).await,
+        ("POST", "/volume") => {
+            // Forms and `no-cors` requests can't send JSON, which rules them out even if `Origin` was stripped.
+            let is_json = request.content_type.as_deref().is_some_and(|content_type| {
+                let media_type = content_type.split(';').next().unwrap_or_default();
+                media_type.trim().eq_ignore_ascii_case("application/json")
+            });
+
+            if !is_json {
+                return (
+                    "415 Unsupported Media Type",
+                    json!({ "error": "`Content-Type` has to be `application/json`" }),
+                );
+            }
+
+            // JSON can't represent NaN or infinity, so the level is always finite.
+            let level = match serde_json::from_slice::<SetVolumeRequest>(&request.body) {
+                Ok(SetVolumeRequest { level }) => level,
+                Err(e) => return ("400 Bad Request", json!({ "error": e.to_string() })),
+            };
+
+            if let Err(e) = command_sender.send(AudioThreadCommand::SetVolume(level)) {
+                return (
+                    "500 Internal Server Error",
+                    json!({ "error": format!("failed to send command to audio thread: {e}") }),
+                );
+            }
+
+            volume_response(command_sender).await
+        }
+        (_, "/volume") => (
+            "405 Method Not Allowed",
+            json!({ "error": "only GET and POST are supported" }),
+        ),
+        (_, path) => (
+            "404 Not Found",
+            json!({ "error": format!("unknown path `{path}`") }),
+        ),
+    }
+}
+
+/// Reads the volume through the audio thread, since `volume_watch` leaves out changes made through the app.
+async fn volume_response(
+    command_sender: &mpsc::Sender<AudioThreadCommand>,
+) -> (&'static str, serde_json::Value) {
+    match read_volume_state(command_sender).await {
+        Ok(state) => (
+            "200 OK",
+            json!({
+                "volume": state.map(|state| state.volume),
+                "muted": state.is_some_and(|state| state.muted),
+            }),
+        ),
+        Err(e) => ("500 Internal Server Error", json!({ "error": e })),
+    }
+}
+
+/// Whether a `Host` header names this machine, with or without the port.
+fn is_local_host(host: &str) -> bool {
+    let name = host.rsplit_once(':').map_or(host, |(name, _port)| name);
+    name == "127.0.0.1" || name.eq_ignore_ascii_case("localhost")
+}
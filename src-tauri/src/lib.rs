@@ -1,80 +1,1114 @@
-use tauri::{Emitter as _, Listener as _};
-use tokio::sync::oneshot;
+#[cfg(windows)]
+use std::sync::mpsc;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use tauri::{App, AppHandle, Emitter as _, Listener as _, Manager as _, State};
+use tokio::sync::watch;
+#[cfg(windows)]
+use tokio::sync::{broadcast, oneshot};
+
+mod format;
+#[cfg(all(windows, feature = "http-server"))]
+mod http;
+mod panic_hook;
+#[cfg(all(windows, feature = "named-pipe"))]
+mod pipe;
+
+#[cfg(all(not(windows), not(all(target_os = "linux", feature = "pulseaudio"))))]
+use volume_sync_core::NoopBackend;
+#[cfg(all(target_os = "linux", feature = "pulseaudio"))]
+use volume_sync_core::PulseBackend;
+#[cfg(not(windows))]
+use volume_sync_core::VolumeBackend;
+#[cfg(windows)]
+use volume_sync_core::{
+    AudioMonitor, AudioThreadCommand, DeviceState, ErrorEvent, ExternalVolumeChange, FadeCurve,
+    MixFormat, OutputDeviceInfo, Preset, SessionInfo,
+};
+use volume_sync_core::{VolumeLimits, VolumeState};
+
+/// Whether the current state is sent to the frontend as soon as the app starts.
+/// When disabled, nothing is sent until the state changes or the frontend emits `frontend-ready`.
+const EMIT_INITIAL_STATE: bool = true;
+
+/// How much of the remaining distance to a new volume the frontend is moved each [`VOLUME_SMOOTHING_STEP`],
+/// so changes made outside the app (like dragging the Windows slider) don't look jittery.
+/// `None` sends every change to the frontend as-is. Only affects what's shown, not the device volume.
+#[cfg(windows)]
+const VOLUME_SMOOTHING: Option<f32> = None;
+
+#[cfg(windows)]
+const VOLUME_SMOOTHING_STEP: Duration = Duration::from_millis(16);
+
+/// How long to wait after a change before sending it to the frontend, so bursts of changes (like from dragging
+/// a hardware volume knob) are sent as their latest value instead of flooding the webview. Zero sends every change.
+const EMIT_DEBOUNCE: Duration = Duration::from_millis(16);
+
+/// Whether the volume set by the app is saved, and restored the next time it starts.
+#[cfg(windows)]
+const PERSIST_VOLUME: bool = true;
+
+/// Name of the file in the app data directory that the volume is saved to.
+#[cfg(windows)]
+const SAVED_VOLUME_FILE_NAME: &str = "volume.json";
+
+/// Name of the file in the app data directory that volume presets are saved to.
+#[cfg(windows)]
+const PRESETS_FILE_NAME: &str = "presets.json";
+
+/// Name of the file in the temp directory that panics are logged to.
+const PANIC_LOG_FILE_NAME: &str = "volume-sync-panics.log";
+
+#[cfg(windows)]
+#[derive(Debug, thiserror::Error)]
+enum StartupError {
+    #[error("failed to start audio monitor: {0}")]
+    MonitorPanicked(String),
+    #[error("audio monitor thread exited without reporting back")]
+    MonitorExited,
+}
+
+/// Sent with `system-volume-changed`. Tagged with `state`, so the frontend can tell a missing device
+/// (like a Bluetooth headset that's pairing) apart from a volume of 0.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "state", rename_all = "kebab-case")]
+enum VolumeEvent {
+    NoDevice,
+    Level(VolumePayload),
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct VolumePayload {
+    level: f32,
+    /// The level as a percentage, formatted for the user's locale.
+    formatted: String,
+    /// The level as a fraction of the cap, matching what `web-volume-fraction-changed` expects.
+    fraction_of_max: f32,
+    /// Set when the level is at one of the ends of the range the app allows.
+    at_limit: Option<VolumeLimit>,
+    muted: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum VolumeLimit {
+    Min,
+    Max,
+}
+
+impl VolumeEvent {
+    fn new(state: &Option<VolumeState>, limits: &VolumeLimits) -> Self {
+        let Some(state) = state else {
+            return Self::NoDevice;
+        };
+
+        let level = state.volume;
+
+        // The limits are device volumes, while the level has already been mapped.
+        let min = limits.mapping.app_volume(limits.min);
+        let max = limits.mapping.app_volume(limits.max);
 
+        Self::Level(VolumePayload {
+            level,
+            formatted: format::percent(level),
+            // A cap of 0.0 leaves nothing to be a fraction of, and the level can only be at it.
+            fraction_of_max: if max > 0.0 { level / max } else { 1.0 },
+            at_limit: VolumeLimit::reached_by(level, min, max),
+            muted: state.muted,
+        })
+    }
+}
+
+impl VolumeLimit {
+    fn reached_by(level: f32, min: f32, max: f32) -> Option<Self> {
+        if level <= min {
+            Some(Self::Min)
+        } else if level >= max {
+            Some(Self::Max)
+        } else {
+            None
+        }
+    }
+}
+
+/// Sent by the frontend with `web-channel-volume-changed`.
+#[cfg(windows)]
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+struct ChannelVolumeRequest {
+    channel: u32,
+    level: f32,
+}
+
+/// Sent by the frontend with `web-session-volume-changed`.
+#[cfg(windows)]
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+struct SessionVolumeRequest {
+    pid: u32,
+    level: f32,
+}
+
+/// Sent by the frontend with `web-volume-faded`.
 #[cfg(windows)]
-mod windows;
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+struct FadeRequest {
+    target: f32,
+    duration_ms: u32,
+    /// Linear if the frontend leaves it out.
+    #[serde(default)]
+    curve: FadeCurve,
+}
+
+/// Sent with `device-changed`, or `None` if there is no device.
 #[cfg(windows)]
-use windows::{AudioMonitor, AudioThreadCommand};
+#[derive(Debug, Clone, Serialize)]
+struct DevicePayload {
+    id: String,
+    /// The friendly name, or the ID if the device doesn't have one.
+    name: String,
+}
 
+#[cfg(windows)]
+impl DevicePayload {
+    fn new(id: &Option<String>, name: &Option<String>) -> Option<Self> {
+        let id = id.clone()?;
+
+        Some(Self {
+            name: name.clone().unwrap_or_else(|| id.clone()),
+            id,
+        })
+    }
+}
+
+/// The ID of the current device, managed separately since it has the same type as other receivers.
+#[cfg(windows)]
+struct CurrentDeviceId(watch::Receiver<Option<String>>);
+
+/// When the audio thread was last responsive, in milliseconds since the Unix epoch.
+#[cfg(windows)]
+struct AudioThreadHeartbeat(Arc<AtomicU64>);
+
+/// Everything the app needs from the `AudioMonitor`, which has to stay on its own thread.
+#[cfg(windows)]
+struct MonitorData {
+    volume_events: watch::Receiver<Option<VolumeState>>,
+    volume_sequence: Arc<AtomicU64>,
+    heartbeat: Arc<AtomicU64>,
+    balance_events: watch::Receiver<Option<f32>>,
+    device_name_events: watch::Receiver<Option<String>>,
+    device_id_events: watch::Receiver<Option<String>>,
+    peak_events: watch::Receiver<f32>,
+    error_events: broadcast::Receiver<ErrorEvent>,
+    #[cfg(feature = "loopback-meter")]
+    output_level_events: watch::Receiver<Option<volume_sync_core::OutputLevel>>,
+    devices_events: watch::Receiver<Vec<DeviceState>>,
+    limits_events: watch::Receiver<VolumeLimits>,
+    command_sender: mpsc::Sender<AudioThreadCommand>,
+}
+
+#[cfg(windows)]
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    // Panics in COM callbacks never make it back to us, and there's no console in release builds.
+    panic_hook::install(Some(std::env::temp_dir().join(PANIC_LOG_FILE_NAME)));
+
     // All of this is necessary because `WebView2` initializes COM on this thread, which interferes with doing it in `AudioMonitor`.
     // More info: https://github.com/tauri-apps/tauri/issues/6485
     let (finished_tx, finished_rx) = oneshot::channel();
     let (monitor_data_tx, monitor_data_rx) = oneshot::channel();
 
-    std::thread::spawn(move || {
-        let monitor = AudioMonitor::new();
+    let monitor_thread = std::thread::spawn(move || {
+        // Report failures back instead of just dying, so `run()` can tell the user what happened.
+        let monitor = match std::panic::catch_unwind(AudioMonitor::default) {
+            Ok(monitor) => monitor,
+            Err(panic) => {
+                let error = StartupError::MonitorPanicked(panic_hook::message(&*panic));
+
+                if monitor_data_tx.send(Err(error)).is_err() {
+                    eprintln!("failed to report audio monitor startup failure");
+                }
+
+                return;
+            }
+        };
+
+        let sent = monitor_data_tx.send(Ok(MonitorData {
+            volume_events: monitor.volume_watch.clone(),
+            volume_sequence: monitor.volume_sequence.clone(),
+            heartbeat: monitor.heartbeat.clone(),
+            balance_events: monitor.balance_watch.clone(),
+            device_name_events: monitor.device_name_watch.clone(),
+            device_id_events: monitor.device_id_watch.clone(),
+            peak_events: monitor.peak_watch.clone(),
+            // Subscribed here, so errors from before the app is set up aren't missed.
+            error_events: monitor.errors.subscribe(),
+            #[cfg(feature = "loopback-meter")]
+            output_level_events: monitor.output_level_watch.clone(),
+            devices_events: monitor.devices_watch.clone(),
+            limits_events: monitor.limits_watch.clone(),
+            command_sender: monitor.command_sender.clone(),
+        }));
 
-        monitor_data_tx
-            .send((monitor.volume_watch.clone(), monitor.command_sender.clone()))
-            .expect("should be able to send monitor data back from thread");
+        // `run()` has already given up, so there's nobody to keep the monitor alive for.
+        if sent.is_err() {
+            eprintln!("failed to send monitor data back from thread, shutting down monitor");
+            return;
+        }
 
         if let Err(e) = finished_rx.blocking_recv() {
             eprintln!("tauri panicked, shutting down monitor thread: {e}");
         }
     });
 
-    let (mut volume_events, command_sender) = monitor_data_rx.blocking_recv().unwrap();
+    // The sender is dropped if the thread exits without sending, so this can't wait forever.
+    let monitor_data = monitor_data_rx
+        .blocking_recv()
+        .unwrap_or(Err(StartupError::MonitorExited));
+
+    let MonitorData {
+        volume_events,
+        volume_sequence,
+        heartbeat,
+        balance_events,
+        device_name_events,
+        device_id_events,
+        peak_events,
+        error_events,
+        #[cfg(feature = "loopback-meter")]
+        output_level_events,
+        devices_events,
+        limits_events,
+        command_sender,
+    } = match monitor_data {
+        Ok(monitor_data) => monitor_data,
+        Err(e) => {
+            eprintln!("{e}");
+            show_fatal_error(&e.to_string());
+            return;
+        }
+    };
 
     tauri::Builder::default()
         .setup(|app| {
             let handle = app.handle().clone();
 
-            app.listen("web-volume-changed", move |evt| {
-                let volume: f32 = match serde_json::from_str(evt.payload()) {
-                    Ok(vol) => vol,
-                    Err(e) => {
-                        eprintln!("failed to parse request from frontend: {e}");
-                        return;
-                    }
-                };
+            #[cfg(all(windows, feature = "named-pipe"))]
+            tauri::async_runtime::spawn(pipe::serve(command_sender.clone()));
 
-                if let Err(e) = command_sender.send(AudioThreadCommand::SetVolume(volume)) {
-                    eprintln!("failed to send volume request: {e}");
-                }
-            });
+            #[cfg(feature = "http-server")]
+            tauri::async_runtime::spawn(http::serve(command_sender.clone()));
 
-            tauri::async_runtime::spawn({
-                async move {
-                    // Send the initial volume (do-while would be nice here).
-                    if let Err(e) = handle.emit("system-volume-changed", *volume_events.borrow()) {
-                        eprintln!("failed to send volume event to frontend: {e}");
-                    }
+            // Only what the frontend shows is smoothed, the pipe and HTTP clients get exact values.
+            let (volume_events, volume_sequence) = match VOLUME_SMOOTHING {
+                Some(factor) => {
+                    let (smoothed_tx, smoothed_rx) = watch::channel(*volume_events.borrow());
+                    tauri::async_runtime::spawn(smooth_volume(volume_events, smoothed_tx, factor));
 
-                    loop {
-                        if let Err(e) = volume_events.changed().await {
-                            eprintln!("failed to listen to system volume events: {e}");
-                            break;
-                        }
+                    // The sequence counts changes to the exact values, so it doesn't apply to the smoothed ones.
+                    (smoothed_rx, None)
+                }
+                None => (volume_events, Some(volume_sequence)),
+            };
+
+            match app.path().app_data_dir() {
+                Ok(dir) => {
+                    if PERSIST_VOLUME {
+                        let path = dir.join(SAVED_VOLUME_FILE_NAME);
 
                         if let Err(e) =
-                            handle.emit("system-volume-changed", *volume_events.borrow())
+                            command_sender.send(AudioThreadCommand::SetPersistencePath(Some(path)))
                         {
-                            eprintln!("failed to send volume event to frontend: {e}");
+                            eprintln!("failed to send persistence path to audio thread: {e}");
                         }
                     }
+
+                    let path = dir.join(PRESETS_FILE_NAME);
+
+                    if let Err(e) =
+                        command_sender.send(AudioThreadCommand::SetPresetsPath(Some(path)))
+                    {
+                        eprintln!("failed to send presets path to audio thread: {e}");
+                    }
+                }
+                Err(e) => eprintln!(
+                    "failed to find app data directory, volume and presets won't be saved: {e}"
+                ),
+            }
+
+            app.manage(command_sender.clone());
+            app.manage(AudioThreadHeartbeat(heartbeat));
+            app.manage(CurrentDeviceId(device_id_events.clone()));
+            app.manage(limits_events.clone());
+
+            listen_for_command(
+                app,
+                "web-volume-changed",
+                command_sender.clone(),
+                AudioThreadCommand::SetVolume,
+            );
+            listen_for_command(
+                app,
+                "web-volume-unmuted",
+                command_sender.clone(),
+                AudioThreadCommand::SetVolumeAndUnmute,
+            );
+            listen_for_command(
+                app,
+                "web-volume-fraction-changed",
+                command_sender.clone(),
+                AudioThreadCommand::SetVolumeFractionOfMax,
+            );
+            listen_for_command(
+                app,
+                "web-volume-adjusted",
+                command_sender.clone(),
+                AudioThreadCommand::AdjustVolume,
+            );
+            listen_for_command(
+                app,
+                "web-volume-faded",
+                command_sender.clone(),
+                |FadeRequest {
+                     target,
+                     duration_ms,
+                     curve,
+                 }| AudioThreadCommand::FadeVolume {
+                    target,
+                    duration_ms,
+                    curve,
+                },
+            );
+            listen_for_command(
+                app,
+                "web-volume-db-changed",
+                command_sender.clone(),
+                AudioThreadCommand::SetVolumeDb,
+            );
+            listen_for_command(app, "web-resend-state", command_sender.clone(), |()| {
+                AudioThreadCommand::ResendState
+            });
+            listen_for_command(
+                app,
+                "web-session-volume-changed",
+                command_sender.clone(),
+                |SessionVolumeRequest { pid, level }| AudioThreadCommand::SetSessionVolume {
+                    pid,
+                    level,
+                },
+            );
+            listen_for_command(
+                app,
+                "web-preset-saved",
+                command_sender.clone(),
+                AudioThreadCommand::SavePreset,
+            );
+            listen_for_command(
+                app,
+                "web-preset-recalled",
+                command_sender.clone(),
+                AudioThreadCommand::RecallPreset,
+            );
+            listen_for_command(app, "web-restore-saved", command_sender.clone(), |()| {
+                AudioThreadCommand::RestoreSaved
+            });
+            listen_for_command(
+                app,
+                "web-balance-changed",
+                command_sender.clone(),
+                AudioThreadCommand::SetBalance,
+            );
+            listen_for_command(
+                app,
+                "web-mute-changed",
+                command_sender.clone(),
+                AudioThreadCommand::SetMute,
+            );
+            listen_for_command(app, "web-mute-toggled", command_sender.clone(), |()| {
+                AudioThreadCommand::ToggleMute
+            });
+            listen_for_command(
+                app,
+                "web-channel-volume-changed",
+                command_sender.clone(),
+                |ChannelVolumeRequest { channel, level }| AudioThreadCommand::SetChannelVolume {
+                    channel,
+                    level,
+                },
+            );
+            listen_for_command(
+                app,
+                "web-mixer-state-applied",
+                command_sender.clone(),
+                AudioThreadCommand::ApplyMixerState,
+            );
+            // The frontend only subscribes while the meter is visible, so it's not polled for nothing.
+            listen_for_command(
+                app,
+                "web-peak-meter-changed",
+                command_sender.clone(),
+                AudioThreadCommand::SetPeakMetering,
+            );
+            listen_for_command(
+                app,
+                "web-front-rear-balance-changed",
+                command_sender,
+                AudioThreadCommand::SetFrontRearBalance,
+            );
+
+            app.listen("frontend-ready", {
+                let handle = handle.clone();
+                let command_sender = command_sender.clone();
+                let balance_events = balance_events.clone();
+                let device_name_events = device_name_events.clone();
+                let device_id_events = device_id_events.clone();
+                let limits_events = limits_events.clone();
+
+                move |_| {
+                    // `volume_events` leaves out changes made through the app, so it can be stale.
+                    tauri::async_runtime::spawn({
+                        let handle = handle.clone();
+                        let command_sender = command_sender.clone();
+                        let limits_events = limits_events.clone();
+
+                        async move {
+                            if let Err(e) =
+                                emit_volume_state(&handle, &command_sender, &limits_events).await
+                            {
+                                eprintln!("{e}");
+                            }
+                        }
+                    });
+                    emit_current(
+                        &handle,
+                        "system-balance-changed",
+                        &balance_events,
+                        Clone::clone,
+                    );
+                    emit_current(&handle, "device-changed", &device_id_events, |id| {
+                        DevicePayload::new(id, &device_name_events.borrow())
+                    });
+                }
+            });
+
+            // Changing the limits doesn't resend the volume, `limits-changed` is enough to update the fractions.
+            tauri::async_runtime::spawn(forward_to_frontend(
+                handle.clone(),
+                "system-volume-changed",
+                volume_events,
+                volume_sequence,
+                {
+                    let limits_events = limits_events.clone();
+                    move |state| VolumeEvent::new(state, &limits_events.borrow())
+                },
+            ));
+            tauri::async_runtime::spawn(forward_to_frontend(
+                handle.clone(),
+                "system-balance-changed",
+                balance_events,
+                None,
+                Clone::clone,
+            ));
+            // The name is always sent before the ID, so following the ID is enough.
+            tauri::async_runtime::spawn(forward_to_frontend(
+                handle.clone(),
+                "device-changed",
+                device_id_events,
+                None,
+                move |id| DevicePayload::new(id, &device_name_events.borrow()),
+            ));
+            tauri::async_runtime::spawn(forward_errors(handle.clone(), error_events));
+            tauri::async_runtime::spawn(forward_to_frontend(
+                handle.clone(),
+                "peak-level",
+                peak_events,
+                None,
+                Clone::clone,
+            ));
+            tauri::async_runtime::spawn(forward_to_frontend(
+                handle.clone(),
+                "system-devices-changed",
+                devices_events,
+                None,
+                Clone::clone,
+            ));
+            tauri::async_runtime::spawn(forward_to_frontend(
+                handle.clone(),
+                "limits-changed",
+                limits_events,
+                None,
+                Clone::clone,
+            ));
+
+            #[cfg(feature = "loopback-meter")]
+            tauri::async_runtime::spawn(forward_to_frontend(
+                handle,
+                "system-output-level-changed",
+                output_level_events,
+                None,
+                Clone::clone,
+            ));
+
+            Ok(())
+        })
+        .plugin(tauri_plugin_shell::init())
+        .invoke_handler(tauri::generate_handler![
+            get_all_device_states,
+            list_devices,
+            current_device_id,
+            get_audio_thread_heartbeat,
+            get_volume_change_history,
+            get_mix_format,
+            get_limits,
+            get_volume,
+            request_initial_volume,
+            get_volume_range,
+            get_front_rear_balance,
+            get_channel_volumes,
+            list_presets,
+            list_sessions,
+            mute_all_outputs,
+            unmute_all_outputs
+        ])
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run({
+            let mut monitor_shutdown = Some((finished_tx, monitor_thread));
+
+            move |_, event| {
+                // The process exits right after this, so the monitor has to be dropped here for the volume to be saved.
+                let tauri::RunEvent::Exit = event else {
+                    return;
+                };
+
+                let Some((finished_tx, monitor_thread)) = monitor_shutdown.take() else {
+                    return;
+                };
+
+                if finished_tx.send(()).is_err() {
+                    eprintln!("monitor thread exited before the app did");
+                }
+
+                if monitor_thread.join().is_err() {
+                    eprintln!("monitor thread panicked while shutting down");
                 }
+            }
+        });
+}
+
+/// Only the volume of the default device is supported outside of Windows, and only on Linux with the `pulseaudio` feature.
+/// Otherwise this only brings up the UI, which never sees a device.
+#[cfg(not(windows))]
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    panic_hook::install(Some(std::env::temp_dir().join(PANIC_LOG_FILE_NAME)));
+
+    #[cfg(all(target_os = "linux", feature = "pulseaudio"))]
+    let backend = Arc::new(PulseBackend::new());
+    #[cfg(not(all(target_os = "linux", feature = "pulseaudio")))]
+    let backend = Arc::new(NoopBackend::default());
+
+    tauri::Builder::default()
+        .setup(move |app| {
+            let volume_events = backend.watch();
+
+            app.manage(volume_events.clone());
+            // The limits never change here, but `request_initial_volume` reads them on every platform.
+            app.manage(watch::channel(VolumeLimits::default()).1);
+
+            listen_for(app, "web-volume-changed", {
+                let backend = backend.clone();
+                move |volume| backend.set_volume(volume)
             });
 
+            tauri::async_runtime::spawn(forward_to_frontend(
+                app.handle().clone(),
+                "system-volume-changed",
+                volume_events,
+                None,
+                |state| VolumeEvent::new(state, &VolumeLimits::default()),
+            ));
+
             Ok(())
         })
         .plugin(tauri_plugin_shell::init())
-        .invoke_handler(tauri::generate_handler![])
+        .invoke_handler(tauri::generate_handler![get_volume, request_initial_volume])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
+}
+
+#[cfg(windows)]
+#[tauri::command]
+async fn get_all_device_states(
+    command_sender: State<'_, mpsc::Sender<AudioThreadCommand>>,
+) -> Result<Vec<DeviceState>, String> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+
+    command_sender
+        .send(AudioThreadCommand::GetAllDeviceStates(reply_tx))
+        .map_err(|e| format!("failed to send device state request: {e}"))?;
+
+    reply_rx
+        .await
+        .map_err(|e| format!("audio thread didn't reply with device states: {e}"))
+}
+
+/// Lists the active output devices for a device picker, with the default one flagged.
+#[cfg(windows)]
+#[tauri::command]
+async fn list_devices(
+    command_sender: State<'_, mpsc::Sender<AudioThreadCommand>>,
+) -> Result<Vec<OutputDeviceInfo>, String> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+
+    command_sender
+        .send(AudioThreadCommand::ListOutputDevices(reply_tx))
+        .map_err(|e| format!("failed to send device list request: {e}"))?;
+
+    reply_rx
+        .await
+        .map_err(|e| format!("audio thread didn't reply with device list: {e}"))
+}
+
+#[cfg(windows)]
+#[tauri::command]
+async fn get_volume_change_history(
+    command_sender: State<'_, mpsc::Sender<AudioThreadCommand>>,
+) -> Result<Vec<ExternalVolumeChange>, String> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+
+    command_sender
+        .send(AudioThreadCommand::GetVolumeChangeHistory(reply_tx))
+        .map_err(|e| format!("failed to send volume change history request: {e}"))?;
+
+    reply_rx
+        .await
+        .map_err(|e| format!("audio thread didn't reply with volume change history: {e}"))
+}
+
+#[cfg(windows)]
+#[tauri::command]
+async fn get_mix_format(
+    command_sender: State<'_, mpsc::Sender<AudioThreadCommand>>,
+) -> Result<Option<MixFormat>, String> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+
+    command_sender
+        .send(AudioThreadCommand::GetMixFormat(reply_tx))
+        .map_err(|e| format!("failed to send mix format request: {e}"))?;
+
+    reply_rx
+        .await
+        .map_err(|e| format!("audio thread didn't reply with mix format: {e}"))
+}
+
+#[cfg(windows)]
+#[tauri::command]
+async fn get_front_rear_balance(
+    command_sender: State<'_, mpsc::Sender<AudioThreadCommand>>,
+) -> Result<Option<f32>, String> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+
+    command_sender
+        .send(AudioThreadCommand::GetFrontRearBalance(reply_tx))
+        .map_err(|e| format!("failed to send front/rear balance request: {e}"))?;
+
+    reply_rx
+        .await
+        .map_err(|e| format!("audio thread didn't reply with front/rear balance: {e}"))
+}
+
+#[cfg(windows)]
+#[tauri::command]
+async fn get_channel_volumes(
+    command_sender: State<'_, mpsc::Sender<AudioThreadCommand>>,
+) -> Result<Option<Vec<f32>>, String> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+
+    command_sender
+        .send(AudioThreadCommand::GetChannelVolumes(reply_tx))
+        .map_err(|e| format!("failed to send channel volumes request: {e}"))?;
+
+    reply_rx
+        .await
+        .map_err(|e| format!("audio thread didn't reply with channel volumes: {e}"))
+}
+
+#[cfg(windows)]
+#[tauri::command]
+async fn list_presets(
+    command_sender: State<'_, mpsc::Sender<AudioThreadCommand>>,
+) -> Result<Vec<Preset>, String> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+
+    command_sender
+        .send(AudioThreadCommand::ListPresets(reply_tx))
+        .map_err(|e| format!("failed to send presets request: {e}"))?;
+
+    reply_rx
+        .await
+        .map_err(|e| format!("audio thread didn't reply with presets: {e}"))
+}
+
+#[cfg(windows)]
+#[tauri::command]
+async fn list_sessions(
+    command_sender: State<'_, mpsc::Sender<AudioThreadCommand>>,
+) -> Result<Vec<SessionInfo>, String> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+
+    command_sender
+        .send(AudioThreadCommand::ListSessions(reply_tx))
+        .map_err(|e| format!("failed to send sessions request: {e}"))?;
+
+    reply_rx
+        .await
+        .map_err(|e| format!("audio thread didn't reply with sessions: {e}"))
+}
+
+#[cfg(windows)]
+#[tauri::command]
+async fn mute_all_outputs(
+    command_sender: State<'_, mpsc::Sender<AudioThreadCommand>>,
+) -> Result<usize, String> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+
+    command_sender
+        .send(AudioThreadCommand::MuteAllOutputs(reply_tx))
+        .map_err(|e| format!("failed to send mute all request: {e}"))?;
+
+    reply_rx
+        .await
+        .map_err(|e| format!("audio thread didn't reply to mute all request: {e}"))
+}
+
+#[cfg(windows)]
+#[tauri::command]
+async fn unmute_all_outputs(
+    command_sender: State<'_, mpsc::Sender<AudioThreadCommand>>,
+) -> Result<usize, String> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+
+    command_sender
+        .send(AudioThreadCommand::UnmuteAllOutputs(reply_tx))
+        .map_err(|e| format!("failed to send unmute all request: {e}"))?;
+
+    reply_rx
+        .await
+        .map_err(|e| format!("audio thread didn't reply to unmute all request: {e}"))
+}
+
+/// Lets components that mount after the initial `system-volume-changed` read the volume, which is `None` if there is no device.
+///
+/// Read through the audio thread, since `system-volume-changed` leaves out changes made through the app.
+#[cfg(windows)]
+#[tauri::command]
+async fn get_volume(
+    command_sender: State<'_, mpsc::Sender<AudioThreadCommand>>,
+) -> Result<Option<f32>, String> {
+    let state = read_volume_state(&command_sender).await?;
+    Ok(state.map(|state| state.volume))
+}
+
+/// Lets components that mount after the initial `system-volume-changed` read the volume, which is `None` if there is no device.
+///
+/// The other backends report the app's own changes back as well, so the watch is always up to date.
+#[cfg(not(windows))]
+#[tauri::command]
+fn get_volume(volume: State<'_, watch::Receiver<Option<VolumeState>>>) -> Option<f32> {
+    volume.borrow().map(|state| state.volume)
+}
+
+/// Emits the current volume as `system-volume-changed` again, for pages whose listener missed the initial one.
+/// Unlike `frontend-ready`, this only resends the volume, and the frontend can wait for it to be sent.
+///
+/// Read through the audio thread, like `get_volume`.
+#[cfg(windows)]
+#[tauri::command]
+async fn request_initial_volume(
+    handle: AppHandle,
+    command_sender: State<'_, mpsc::Sender<AudioThreadCommand>>,
+    limits: State<'_, watch::Receiver<VolumeLimits>>,
+) -> Result<(), String> {
+    emit_volume_state(&handle, &command_sender, &limits).await
+}
+
+/// Emits the current volume as `system-volume-changed` again, for pages whose listener missed the initial one.
+/// Unlike `frontend-ready`, this only resends the volume, and the frontend can wait for it to be sent.
+#[cfg(not(windows))]
+#[tauri::command]
+fn request_initial_volume(
+    handle: AppHandle,
+    volume: State<'_, watch::Receiver<Option<VolumeState>>>,
+    limits: State<'_, watch::Receiver<VolumeLimits>>,
+) {
+    emit_current(&handle, "system-volume-changed", &volume, |state| {
+        VolumeEvent::new(state, &limits.borrow())
+    });
+}
+
+/// Returns `(min_db, max_db, increment_db)` for the current device, or `None` if there is no device.
+#[cfg(windows)]
+#[tauri::command]
+async fn get_volume_range(
+    command_sender: State<'_, mpsc::Sender<AudioThreadCommand>>,
+) -> Result<Option<(f32, f32, f32)>, String> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+
+    command_sender
+        .send(AudioThreadCommand::GetVolumeRange(reply_tx))
+        .map_err(|e| format!("failed to send volume range request: {e}"))?;
+
+    reply_rx
+        .await
+        .map_err(|e| format!("audio thread didn't reply with volume range: {e}"))
+}
+
+/// Lets the frontend lay out its volume slider, which is kept up to date by `limits-changed` after this.
+#[cfg(windows)]
+#[tauri::command]
+fn get_limits(limits: State<'_, watch::Receiver<VolumeLimits>>) -> VolumeLimits {
+    *limits.borrow()
+}
+
+/// Lets the frontend highlight the current device in a list from `list_devices`, or `None` if there is no device.
+#[cfg(windows)]
+#[tauri::command]
+fn current_device_id(device_id: State<'_, CurrentDeviceId>) -> Option<String> {
+    device_id.0.borrow().clone()
+}
+
+/// Lets the frontend warn when the audio thread seems stuck, by comparing the result against the current time.
+#[cfg(windows)]
+#[tauri::command]
+fn get_audio_thread_heartbeat(heartbeat: State<'_, AudioThreadHeartbeat>) -> u64 {
+    heartbeat.0.load(Ordering::Relaxed)
+}
+
+/// Reads the state of the current device through the audio thread, which unlike `volume_watch` includes
+/// changes made through the app, and reflects every command sent before it.
+#[cfg(windows)]
+async fn read_volume_state(
+    command_sender: &mpsc::Sender<AudioThreadCommand>,
+) -> Result<Option<VolumeState>, String> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+
+    command_sender
+        .send(AudioThreadCommand::GetVolumeState(reply_tx))
+        .map_err(|e| format!("failed to send volume state request: {e}"))?;
+
+    reply_rx
+        .await
+        .map_err(|e| format!("audio thread didn't reply with volume state: {e}"))
+}
+
+/// Emits the state of the current device as `system-volume-changed`, read through the audio thread
+/// so it includes changes made through the app.
+#[cfg(windows)]
+async fn emit_volume_state(
+    handle: &AppHandle,
+    command_sender: &mpsc::Sender<AudioThreadCommand>,
+    limits: &watch::Receiver<VolumeLimits>,
+) -> Result<(), String> {
+    let state = read_volume_state(command_sender).await?;
+    let payload = VolumeEvent::new(&state, &limits.borrow());
+
+    handle
+        .emit("system-volume-changed", payload)
+        .map_err(|e| format!("failed to send `system-volume-changed` to frontend: {e}"))
+}
+
+/// Sends a command to the audio thread every time the frontend emits `event`.
+#[cfg(windows)]
+fn listen_for_command<T: DeserializeOwned>(
+    app: &App,
+    event: &'static str,
+    command_sender: mpsc::Sender<AudioThreadCommand>,
+    command: fn(T) -> AudioThreadCommand,
+) {
+    listen_for(app, event, move |value| {
+        if let Err(e) = command_sender.send(command(value)) {
+            eprintln!("failed to send `{event}` request: {e}");
+        }
+    });
+}
+
+/// Calls `handler` with the payload every time the frontend emits `event`.
+fn listen_for<T: DeserializeOwned>(
+    app: &App,
+    event: &'static str,
+    handler: impl Fn(T) + Send + 'static,
+) {
+    app.listen(event, move |evt| {
+        let value: T = match serde_json::from_str(evt.payload()) {
+            Ok(value) => value,
+            Err(e) => {
+                eprintln!("failed to parse `{event}` request from frontend: {e}");
+                return;
+            }
+        };
+
+        handler(value);
+    });
+}
+
+/// Emits `event` to the frontend every time `events` changes, as well as initially if [`EMIT_INITIAL_STATE`] is set.
+///
+/// If the sender counts its updates in `sequence`, values that were overwritten before they could be emitted are
+/// logged in debug builds.
+async fn forward_to_frontend<T, P: Serialize + Clone>(
+    handle: AppHandle,
+    event: &'static str,
+    mut events: watch::Receiver<T>,
+    sequence: Option<Arc<AtomicU64>>,
+    to_payload: impl Fn(&T) -> P,
+) {
+    let mut last_sequence = current_sequence(&events, sequence.as_deref());
+
+    // Send the initial value (do-while would be nice here).
+    if EMIT_INITIAL_STATE {
+        emit_current(&handle, event, &events, &to_payload);
+    }
+
+    loop {
+        if let Err(e) = events.changed().await {
+            eprintln!("failed to listen to `{event}` events: {e}");
+            break;
+        }
+
+        if !EMIT_DEBOUNCE.is_zero() {
+            tokio::time::sleep(EMIT_DEBOUNCE).await;
+
+            // Whatever arrived in the meantime is sent below, so it shouldn't wake up the next iteration.
+            events.mark_unchanged();
+        }
+
+        let new_sequence = current_sequence(&events, sequence.as_deref());
+        let skipped = new_sequence.saturating_sub(last_sequence + 1);
+
+        // Debouncing skips values on purpose, but it's still what explains a brief change that never showed up.
+        if cfg!(debug_assertions) && skipped > 0 {
+            eprintln!(
+                "skipped {skipped} `{event}` values that changed faster than they were emitted (debounced by {EMIT_DEBOUNCE:?})"
+            );
+        }
+
+        last_sequence = new_sequence;
+
+        emit_current(&handle, event, &events, &to_payload);
+    }
+}
+
+/// Sends every error reported by the audio thread to the frontend as `error`, on top of it being logged.
+#[cfg(windows)]
+async fn forward_errors(handle: AppHandle, mut errors: broadcast::Receiver<ErrorEvent>) {
+    loop {
+        match errors.recv().await {
+            Ok(error) => {
+                if let Err(e) = handle.emit("error", error) {
+                    eprintln!("failed to send `error` to frontend: {e}");
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                eprintln!(
+                    "missed {missed} errors that were reported faster than they were emitted"
+                );
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Eases `smoothed` towards every new value of `exact`, stepping `factor` of the remaining distance at a time.
+/// Changes to or from no device are sent immediately.
+#[cfg(windows)]
+async fn smooth_volume(
+    mut exact: watch::Receiver<Option<VolumeState>>,
+    smoothed: watch::Sender<Option<VolumeState>>,
+    factor: f32,
+) {
+    // Anything closer than this is just snapped to.
+    const EPSILON: f32 = 0.001;
+
+    while exact.changed().await.is_ok() {
+        let mut target = *exact.borrow_and_update();
+
+        loop {
+            let current = *smoothed.borrow();
+
+            match (current, target) {
+                (Some(current), Some(target))
+                    if (target.volume - current.volume).abs() > EPSILON =>
+                {
+                    // Only the volume is eased, muting is shown right away.
+                    smoothed.send_replace(Some(VolumeState {
+                        volume: current.volume + (target.volume - current.volume) * factor,
+                        muted: target.muted,
+                    }));
+                }
+                _ => {
+                    smoothed.send_replace(target);
+                    break;
+                }
+            }
+
+            // A new value takes over from the one being eased towards.
+            tokio::select! {
+                () = tokio::time::sleep(VOLUME_SMOOTHING_STEP) => {}
+                changed = exact.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+
+                    target = *exact.borrow_and_update();
+                }
+            }
+        }
+    }
+}
+
+/// Reads `sequence` while `events` is borrowed, so it can't be bumped between the value and the count.
+fn current_sequence<T>(events: &watch::Receiver<T>, sequence: Option<&AtomicU64>) -> u64 {
+    let _value = events.borrow();
+    sequence.map_or(0, |sequence| sequence.load(Ordering::Relaxed))
+}
+
+fn emit_current<T, P: Serialize + Clone>(
+    handle: &AppHandle,
+    event: &'static str,
+    events: &watch::Receiver<T>,
+    to_payload: impl Fn(&T) -> P,
+) {
+    let payload = to_payload(&events.borrow());
+
+    if let Err(e) = handle.emit(event, payload) {
+        eprintln!("failed to send `{event}` to frontend: {e}");
+    }
+}
+
+/// Shows a blocking error dialog, for errors that happen before there's a frontend to report them to.
+#[cfg(windows)]
+fn show_fatal_error(message: &str) {
+    use windows::{
+        core::{w, HSTRING},
+        Win32::{
+            Foundation::HWND,
+            UI::WindowsAndMessaging::{MessageBoxW, MB_ICONERROR, MB_OK},
+        },
+    };
 
-    finished_tx
-        .send(())
-        .expect("monitor thread should be alive");
+    // SAFETY: No owner window is passed, and both strings are valid and null-terminated.
+    unsafe {
+        MessageBoxW(
+            HWND::default(),
+            &HSTRING::from(message),
+            w!("volume-sync"),
+            MB_OK | MB_ICONERROR,
+        )
+    };
 }
@@ -0,0 +1,48 @@
+use std::{any::Any, fs::OpenOptions, io::Write as _, path::PathBuf};
+
+/// Appends every panic to `log_file` if given, and then runs the default hook (which prints it to stderr).
+///
+/// Release builds don't have a console, so without this, panics in places like COM callbacks
+/// would only show up as a failed `HRESULT` or an abort.
+pub fn install(log_file: Option<PathBuf>) {
+    let default_hook = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        if let Some(log_file) = &log_file {
+            let thread = std::thread::current();
+            let location = info
+                .location()
+                .map_or_else(|| "unknown location".to_string(), ToString::to_string);
+
+            let written = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(log_file)
+                .and_then(|mut file| {
+                    writeln!(
+                        file,
+                        "thread `{}` panicked at {location}: {}",
+                        thread.name().unwrap_or("<unnamed>"),
+                        message(info.payload()),
+                    )
+                });
+
+            if let Err(e) = written {
+                eprintln!("failed to write panic to `{}`: {e}", log_file.display());
+            }
+        }
+
+        default_hook(info);
+    }));
+}
+
+/// Extracts the message from a panic payload, which is almost always a string.
+pub fn message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown error".to_string()
+    }
+}
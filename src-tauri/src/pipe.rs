@@ -0,0 +1,106 @@
+use std::sync::mpsc;
+
+use serde_json::json;
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader},
+    net::windows::named_pipe::{NamedPipeServer, ServerOptions},
+};
+
+use volume_sync_core::AudioThreadCommand;
+
+use crate::read_volume_state;
+
+const PIPE_NAME: &str = r"\\.\pipe\volume-sync";
+
+/// Accepts clients on [`PIPE_NAME`] until the pipe fails.
+///
+/// Each client sends one command per line (`get`, `set <level>`, `mute`, `unmute`, `toggle-mute`) and gets a single
+/// line of JSON back, holding the state of the device after the command was applied.
+pub async fn serve(command_sender: mpsc::Sender<AudioThreadCommand>) {
+    let mut server = match ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(PIPE_NAME)
+    {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("failed to create named pipe `{PIPE_NAME}`: {e}");
+            return;
+        }
+    };
+
+    loop {
+        if let Err(e) = server.connect().await {
+            eprintln!("failed to accept named pipe client: {e}");
+            return;
+        }
+
+        // A new instance has to exist before handing off the connected one, otherwise clients can't connect in the meantime.
+        let client = match ServerOptions::new().create(PIPE_NAME) {
+            Ok(next) => std::mem::replace(&mut server, next),
+            Err(e) => {
+                eprintln!("failed to create named pipe `{PIPE_NAME}`: {e}");
+                return;
+            }
+        };
+
+        tauri::async_runtime::spawn(handle_client(client, command_sender.clone()));
+    }
+}
+
+async fn handle_client(client: NamedPipeServer, command_sender: mpsc::Sender<AudioThreadCommand>) {
+    let (reader, mut writer) = tokio::io::split(client);
+    let mut lines = BufReader::new(reader).lines();
+
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("failed to read from named pipe client: {e}");
+                break;
+            }
+        };
+
+        let mut response = handle_request(&line, &command_sender).await.to_string();
+        response.push('\n');
+
+        if let Err(e) = writer.write_all(response.as_bytes()).await {
+            eprintln!("failed to write to named pipe client: {e}");
+            break;
+        }
+    }
+}
+
+async fn handle_request(
+    line: &str,
+    command_sender: &mpsc::Sender<AudioThreadCommand>,
+) -> serde_json::Value {
+    let mut args = line.split_whitespace();
+
+    let command = match (args.next(), args.next(), args.next()) {
+        (Some("get"), None, None) => None,
+        (Some("set"), Some(level), None) => match level.parse::<f32>() {
+            Ok(level) if level.is_finite() => Some(AudioThreadCommand::SetVolume(level)),
+            _ => return json!({ "error": format!("invalid volume level `{level}`") }),
+        },
+        (Some("mute"), None, None) => Some(AudioThreadCommand::SetMute(true)),
+        (Some("unmute"), None, None) => Some(AudioThreadCommand::SetMute(false)),
+        (Some("toggle-mute"), None, None) => Some(AudioThreadCommand::ToggleMute),
+        _ => return json!({ "error": format!("unknown command `{line}`") }),
+    };
+
+    if let Some(command) = command {
+        if let Err(e) = command_sender.send(command) {
+            return json!({ "error": format!("failed to send command to audio thread: {e}") });
+        }
+    }
+
+    // Sent after the command, so the reply shows what it did, which `volume_watch` leaves out.
+    match read_volume_state(command_sender).await {
+        Ok(state) => json!({
+            "volume": state.map(|state| state.volume),
+            "muted": state.is_some_and(|state| state.muted),
+        }),
+        Err(e) => json!({ "error": e }),
+    }
+}
@@ -0,0 +1,48 @@
+//! Prints every change to the volume of the default output device, without the Tauri app.
+//!
+//! Run with `cargo run --example volume_demo`, and change the volume from Windows to see it.
+
+#[cfg(windows)]
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    // The monitor has to stay on the thread that created it, which the current-thread runtime guarantees.
+    let monitor = volume_sync_core::AudioMonitor::default();
+    let mut volume_events = monitor.volume_watch.clone();
+
+    loop {
+        let state = *volume_events.borrow_and_update();
+
+        match state {
+            Some(state) => {
+                let db = volume_db(&monitor).await;
+                let db = db.map_or(String::new(), |db| format!(" ({db:.1} dB)"));
+                let muted = if state.muted { " (muted)" } else { "" };
+
+                println!("volume: {:.0}%{db}{muted}", state.volume * 100.0);
+            }
+            None => println!("no output device"),
+        }
+
+        if volume_events.changed().await.is_err() {
+            break;
+        }
+    }
+}
+
+/// The device volume in decibels, which the watch doesn't carry, so it's read from the audio thread on every change.
+#[cfg(windows)]
+async fn volume_db(monitor: &volume_sync_core::AudioMonitor) -> Option<f32> {
+    let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+
+    monitor
+        .command_sender
+        .send(volume_sync_core::AudioThreadCommand::GetVolumeDb(reply_tx))
+        .ok()?;
+
+    reply_rx.await.ok().flatten()
+}
+
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("this example only works on Windows");
+}
@@ -0,0 +1,16 @@
+use tokio::sync::watch;
+
+use crate::VolumeState;
+
+/// The part of an audio engine that every platform can provide, so the app can run (if not do much) anywhere.
+pub trait VolumeBackend {
+    /// The state of the current device, or `None` if there is no device.
+    fn watch(&self) -> watch::Receiver<Option<VolumeState>>;
+
+    /// Sets the volume of the current device, from 0.0 to 1.0, within whatever limits the backend enforces.
+    fn set_volume(&self, volume: f32);
+
+    fn current(&self) -> Option<VolumeState> {
+        *self.watch().borrow()
+    }
+}
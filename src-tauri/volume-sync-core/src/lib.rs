@@ -0,0 +1,35 @@
+//! The audio engine behind volume-sync, usable without the Tauri app.
+//!
+//! [`AudioMonitor`] follows the default output device and publishes its state through `watch` channels,
+//! and is controlled by sending [`AudioThreadCommand`]s through its `command_sender`.
+//! It only exists on Windows. Linux gets a PulseAudio [`VolumeBackend`] with the `pulseaudio` feature,
+//! and everything else gets one that does nothing.
+
+mod backend;
+#[cfg(all(target_os = "linux", feature = "pulseaudio"))]
+mod linux;
+#[cfg(not(windows))]
+mod noop;
+mod volume;
+#[cfg(windows)]
+mod windows;
+
+/// The `windows` crate that types like `HSTRING` and `GUID` in the API come from, so users don't have to
+/// depend on the exact same version themselves. Renamed, since `windows` is already taken by our own module.
+#[cfg(windows)]
+pub use ::windows as windows_rs;
+pub use backend::VolumeBackend;
+#[cfg(all(target_os = "linux", feature = "pulseaudio"))]
+pub use linux::PulseBackend;
+#[cfg(not(windows))]
+pub use noop::NoopBackend;
+pub use volume::{VolumeLimits, VolumeMapping, VolumeState, MAX_NORMALIZED_VOLUME_LEVEL};
+#[cfg(all(windows, feature = "loopback-meter"))]
+pub use windows::OutputLevel;
+#[cfg(windows)]
+pub use windows::{
+    current_default_volume, AudioError, AudioMonitor, AudioMonitorBuilder, AudioThreadCommand,
+    BuildError, ComApartment, ComInitialization, ConfirmVolumeError, DataFlow, DeviceInfo,
+    DeviceNotification, DeviceRole, DeviceState, ErrorEvent, ErrorKind, ExternalVolumeChange,
+    FadeCurve, MixFormat, MixerState, OutputDeviceInfo, Preset, SessionInfo,
+};
@@ -0,0 +1,259 @@
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::mpsc::{self, RecvTimeoutError},
+    time::Duration,
+};
+
+use libpulse_binding::{
+    callbacks::ListResult,
+    context::{
+        subscribe::{Facility, InterestMaskSet, Operation},
+        Context, FlagSet, State,
+    },
+    mainloop::standard::{IterateResult, Mainloop},
+    volume::{ChannelVolumes, Volume},
+};
+use tokio::sync::watch;
+
+use crate::{VolumeBackend, VolumeState, MAX_NORMALIZED_VOLUME_LEVEL};
+
+/// How long the PulseAudio thread waits for a command before handling PulseAudio events again.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug)]
+enum PulseCommand {
+    SetVolume(f32),
+}
+
+/// Follows the default PulseAudio sink, like [`AudioMonitor`](crate::AudioMonitor) does with the default device on Windows.
+///
+/// PulseAudio has no way to tag our own changes, so unlike on Windows, they are reported back through the watch as well.
+#[derive(Debug)]
+pub struct PulseBackend {
+    volume_watch: watch::Receiver<Option<VolumeState>>,
+    command_sender: mpsc::Sender<PulseCommand>,
+}
+
+impl PulseBackend {
+    /// Connects to PulseAudio on its own thread, which runs until the backend is dropped.
+    /// If the connection fails, the error is logged and there's never a device.
+    pub fn new() -> Self {
+        let (command_tx, command_rx) = mpsc::channel();
+        let (volume_tx, volume_rx) = watch::channel(None);
+
+        std::thread::spawn(move || pulse_thread(command_rx, volume_tx));
+
+        Self {
+            volume_watch: volume_rx,
+            command_sender: command_tx,
+        }
+    }
+}
+
+impl Default for PulseBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VolumeBackend for PulseBackend {
+    fn watch(&self) -> watch::Receiver<Option<VolumeState>> {
+        self.volume_watch.clone()
+    }
+
+    fn set_volume(&self, volume: f32) {
+        if let Err(e) = self.command_sender.send(PulseCommand::SetVolume(volume)) {
+            eprintln!("failed to send volume to PulseAudio thread: {e}");
+        }
+    }
+}
+
+/// What the PulseAudio callbacks found out, for the thread to act on between iterations of the main loop.
+/// The callbacks can't make requests of their own, since they don't have access to the context.
+#[derive(Default)]
+struct SinkState {
+    default_sink: Option<String>,
+    /// The volume of every channel of the default sink, which setting the volume has to keep the balance of.
+    channel_volumes: Option<ChannelVolumes>,
+    refresh_server: bool,
+    refresh_sink: bool,
+}
+
+fn pulse_thread(
+    commands: mpsc::Receiver<PulseCommand>,
+    volume_watch: watch::Sender<Option<VolumeState>>,
+) {
+    let Some(mut mainloop) = Mainloop::new() else {
+        eprintln!("failed to create PulseAudio main loop");
+        return;
+    };
+
+    let Some(mut context) = Context::new(&mainloop, "volume-sync") else {
+        eprintln!("failed to create PulseAudio context");
+        return;
+    };
+
+    if let Err(e) = context.connect(None, FlagSet::NOFLAGS, None) {
+        eprintln!("failed to connect to PulseAudio: {e}");
+        return;
+    }
+
+    // Every way out of the loops goes through here, so the connection is always closed.
+    run(&mut mainloop, &mut context, commands, volume_watch);
+
+    context.disconnect();
+}
+
+/// Waits for the connection to be ready, then follows the default sink until the main loop stops or the backend
+/// is dropped.
+fn run(
+    mainloop: &mut Mainloop,
+    context: &mut Context,
+    commands: mpsc::Receiver<PulseCommand>,
+    volume_watch: watch::Sender<Option<VolumeState>>,
+) {
+    loop {
+        if !iterate(mainloop, true) {
+            return;
+        }
+
+        match context.get_state() {
+            State::Ready => break,
+            State::Failed | State::Terminated => {
+                eprintln!("failed to connect to PulseAudio");
+                return;
+            }
+            _ => {}
+        }
+    }
+
+    // The callbacks only ever run on this thread, during `iterate()`.
+    let volume_watch = Rc::new(volume_watch);
+
+    // Look up the default sink once at startup, so the watch isn't empty until something changes.
+    let state = Rc::new(RefCell::new(SinkState {
+        refresh_server: true,
+        ..SinkState::default()
+    }));
+
+    // The server reports a new default sink, and sinks report their volume changes.
+    context.set_subscribe_callback(Some(Box::new({
+        let state = state.clone();
+        move |facility, operation, _index| {
+            let mut state = state.borrow_mut();
+
+            match (facility, operation) {
+                (Some(Facility::Server), _) => state.refresh_server = true,
+                (Some(Facility::Sink), Some(Operation::Changed | Operation::Removed)) => {
+                    state.refresh_sink = true;
+                }
+                _ => {}
+            }
+        }
+    })));
+    context.subscribe(InterestMaskSet::SERVER | InterestMaskSet::SINK, |_| {});
+
+    let mut introspector = context.introspect();
+
+    loop {
+        if !iterate(mainloop, false) {
+            return;
+        }
+
+        let (refresh_server, refresh_sink, default_sink) = {
+            let mut state = state.borrow_mut();
+            (
+                std::mem::take(&mut state.refresh_server),
+                std::mem::take(&mut state.refresh_sink),
+                state.default_sink.clone(),
+            )
+        };
+
+        if refresh_server {
+            let state = state.clone();
+            introspector.get_server_info(move |info| {
+                let mut state = state.borrow_mut();
+                state.default_sink = info.default_sink_name.as_ref().map(|name| name.to_string());
+                state.refresh_sink = true;
+            });
+        }
+
+        if refresh_sink {
+            match default_sink {
+                Some(ref name) => {
+                    let state = state.clone();
+                    let volume_watch = volume_watch.clone();
+
+                    introspector.get_sink_info_by_name(name, move |result| match result {
+                        ListResult::Item(info) => {
+                            state.borrow_mut().channel_volumes = Some(info.volume);
+
+                            volume_watch.send_replace(Some(VolumeState {
+                                volume: normalize(info.volume.max()),
+                                muted: info.mute,
+                            }));
+                        }
+                        ListResult::Error => {
+                            // The sink was removed, and the server will announce the new default shortly.
+                            state.borrow_mut().channel_volumes = None;
+                            volume_watch.send_replace(None);
+                        }
+                        ListResult::End => {}
+                    });
+                }
+                None => {
+                    state.borrow_mut().channel_volumes = None;
+                    volume_watch.send_replace(None);
+                }
+            }
+        }
+
+        let command = match commands.recv_timeout(POLL_INTERVAL) {
+            Ok(command) => command,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        match command {
+            PulseCommand::SetVolume(volume) => {
+                let state = state.borrow();
+
+                let (Some(name), Some(mut channel_volumes)) =
+                    (state.default_sink.as_deref(), state.channel_volumes)
+                else {
+                    continue;
+                };
+
+                // Clamping keeps NaN, which would mute the sink once it's cast to an integer.
+                if !volume.is_finite() {
+                    eprintln!("ignoring a volume of {volume}");
+                    continue;
+                }
+
+                let volume = volume.clamp(0.0, MAX_NORMALIZED_VOLUME_LEVEL);
+
+                // Scaling the loudest channel keeps the balance between them.
+                channel_volumes.scale(Volume((volume * Volume::NORMAL.0 as f32).round() as u32));
+                introspector.set_sink_volume_by_name(name, &channel_volumes, None);
+            }
+        }
+    }
+}
+
+/// Runs a single iteration of the main loop, and returns whether it's still running.
+fn iterate(mainloop: &mut Mainloop, block: bool) -> bool {
+    match mainloop.iterate(block) {
+        IterateResult::Success(_) => true,
+        IterateResult::Quit(_) => false,
+        IterateResult::Err(e) => {
+            eprintln!("PulseAudio main loop failed: {e}");
+            false
+        }
+    }
+}
+
+/// Maps a PulseAudio volume to 0.0..=1.0 like the Windows scalar, where 1.0 is 100% and amplification is cut off.
+fn normalize(volume: Volume) -> f32 {
+    (volume.0 as f32 / Volume::NORMAL.0 as f32).clamp(0.0, 1.0)
+}
@@ -0,0 +1,24 @@
+use tokio::sync::watch;
+
+use crate::{VolumeBackend, VolumeState};
+
+/// A backend for platforms without a real one, which never has a device and ignores every change.
+#[derive(Debug)]
+pub struct NoopBackend {
+    volume: watch::Sender<Option<VolumeState>>,
+}
+
+impl Default for NoopBackend {
+    fn default() -> Self {
+        let (volume, _) = watch::channel(None);
+        Self { volume }
+    }
+}
+
+impl VolumeBackend for NoopBackend {
+    fn watch(&self) -> watch::Receiver<Option<VolumeState>> {
+        self.volume.subscribe()
+    }
+
+    fn set_volume(&self, _volume: f32) {}
+}
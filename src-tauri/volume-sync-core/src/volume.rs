@@ -0,0 +1,66 @@
+use serde::Serialize;
+
+/// The highest volume the app will set, unless explicitly asked not to, until it's changed with
+/// the `SetMaxVolume` command.
+pub const MAX_NORMALIZED_VOLUME_LEVEL: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct VolumeState {
+    pub volume: f32,
+    pub muted: bool,
+}
+
+/// An affine mapping between the volume the app works with and the volume of the device,
+/// so the whole range of a slider can be spent on the part of the device's range that's actually used.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct VolumeMapping {
+    pub gain: f32,
+    pub offset: f32,
+}
+
+impl Default for VolumeMapping {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            offset: 0.0,
+        }
+    }
+}
+
+impl VolumeMapping {
+    pub(crate) fn is_valid(&self) -> bool {
+        let in_range = |volume: f32| (0.0..=1.0).contains(&volume);
+
+        // The mapping can't be inverted without a gain.
+        self.gain != 0.0 && in_range(self.device_volume(0.0)) && in_range(self.device_volume(1.0))
+    }
+
+    pub(crate) fn device_volume(self, volume: f32) -> f32 {
+        volume * self.gain + self.offset
+    }
+
+    /// Maps a device volume back to the volume the app works with, which is always within 0.0..=1.0.
+    pub fn app_volume(self, volume: f32) -> f32 {
+        ((volume - self.offset) / self.gain).clamp(0.0, 1.0)
+    }
+}
+
+/// Everything that decides which volumes the app can set, so a UI can lay out its controls to match.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct VolumeLimits {
+    /// The lowest device volume the app sets.
+    pub min: f32,
+    /// The highest device volume the app sets, unless explicitly asked not to.
+    pub max: f32,
+    pub mapping: VolumeMapping,
+}
+
+impl Default for VolumeLimits {
+    fn default() -> Self {
+        Self {
+            min: 0.0,
+            max: MAX_NORMALIZED_VOLUME_LEVEL,
+            mapping: VolumeMapping::default(),
+        }
+    }
+}
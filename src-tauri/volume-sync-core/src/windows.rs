@@ -0,0 +1,3933 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, oneshot, watch};
+
+use crate::{VolumeBackend, VolumeLimits, VolumeMapping, VolumeState, MAX_NORMALIZED_VOLUME_LEVEL};
+use windows::{
+    core::*,
+    Win32::{
+        Devices::FunctionDiscovery::PKEY_Device_FriendlyName,
+        Foundation::{
+            ERROR_NOT_FOUND, E_ACCESSDENIED, E_NOINTERFACE, RPC_E_CHANGED_MODE, S_FALSE, S_OK,
+        },
+        Media::Audio::{Endpoints::*, *},
+        System::Com::*,
+        UI::Shell::PropertiesSystem::{IPropertyStore, PROPERTYKEY},
+    },
+};
+
+#[cfg(feature = "loopback-meter")]
+mod loopback;
+mod sessions;
+
+#[cfg(feature = "loopback-meter")]
+pub use loopback::OutputLevel;
+pub use sessions::SessionInfo;
+
+pub type NotSendMarker = PhantomData<*const ()>;
+/// Called with the notification and the volume of every channel, which the struct alone can't hold.
+pub type VolumeCallbackFn<T> =
+    fn(&AUDIO_VOLUME_NOTIFICATION_DATA, &[f32], &T) -> windows_core::Result<()>;
+
+/// How long a new default device has to stay the default before it's acquired.
+const DEFAULT_DEVICE_SETTLE_DELAY: Duration = Duration::from_millis(100);
+
+/// How long the set of devices has to stay unchanged before it's enumerated again.
+const DEVICE_LIST_SETTLE_DELAY: Duration = Duration::from_millis(250);
+
+/// How often the volume is raised while slew limiting is ramping it up.
+const SLEW_STEP_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How often the volume is moved while fading, which is fine enough that the steps can't be heard.
+const FADE_STEP_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long the volume has to stay unchanged before it's saved, so dragging a slider doesn't write the file on every step.
+const SAVE_DELAY: Duration = Duration::from_millis(500);
+
+/// How far below the cap a dB volume has to be, by the approximation in [`db_to_scalar`], to be set exactly.
+/// Closer to the cap, it's set as an approximate scalar instead, so it can't briefly go above it.
+const DB_CAP_MARGIN: f32 = 6.0;
+
+/// How often the peak level of the current device is read while metering is enabled, for roughly 30 updates per second.
+const PEAK_POLL_INTERVAL: Duration = Duration::from_millis(33);
+
+/// How many device notifications a slow subscriber can fall behind by before it starts missing them.
+const DEVICE_NOTIFICATION_CAPACITY: usize = 32;
+
+/// How many errors a slow subscriber can fall behind by before it starts missing them.
+const ERROR_EVENT_CAPACITY: usize = 16;
+
+/// How many external volume changes are kept for [`AudioThreadCommand::GetVolumeChangeHistory`].
+const VOLUME_CHANGE_HISTORY_LENGTH: usize = 16;
+
+/// How often the audio thread wakes up to update its heartbeat when there's nothing else to do.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many times the audio thread tries to create its device enumerator before giving up, and how long it waits in between.
+const DEVICE_ENUMERATOR_ATTEMPTS: u32 = 10;
+const DEVICE_ENUMERATOR_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+// We need to indicate that a volume change comes from us, so we can avoid sending it to the frontend.
+// The actual GUID here doesn't matter, I just generated one.
+const LOCAL_VOLUME_CHANGE_GUID: GUID = GUID::from_u128(0xdc1b615d_6d18_4f6e_af33_488e23d0dc6a);
+
+// Changes applied to mirror targets use their own GUID. They should still reach the frontend if the target
+// is the current device, but must not be mirrored again, or two devices mirroring each other would loop forever.
+const MIRROR_VOLUME_CHANGE_GUID: GUID = GUID::from_u128(0xbcdd4ca9_74f0_4477_b94f_62561bfcbd37);
+
+// `WAVE_FORMAT_EXTENSIBLE` from `mmreg.h`, which would need all of `Win32_Media_KernelStreaming` for a single constant.
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+// Speaker positions from `ksmedia.h`, grouped by which half of the room they're in.
+// The centre front and low-frequency speakers aren't in either group, since they're shared by both.
+const FRONT_SPEAKERS: u32 = 0x1 | 0x2 | 0x40 | 0x80;
+const REAR_SPEAKERS: u32 = 0x10 | 0x20 | 0x100 | 0x200 | 0x400;
+
+// `PKEY_Device_ContainerId` from `devpkey.h`, which isn't exposed by the `windows` crate as a `PROPERTYKEY`.
+const PKEY_DEVICE_CONTAINER_ID: PROPERTYKEY = PROPERTYKEY {
+    fmtid: GUID::from_u128(0x8c7ed206_3f8a_4827_b3ab_ae9e1faefc6c),
+    pid: 2,
+};
+
+/// Commands handled by the audio thread.
+///
+/// There is a single receiver, so commands are handled one at a time, in the order they were sent, no matter how
+/// many senders there are. When several sources set the volume at once, the last one to send wins, and the device
+/// never ends up in between. Sets made with our own event context aren't reported back through `volume_watch`,
+/// so their echoes can't interleave there either.
+pub enum AudioThreadCommand {
+    NewDefault(HSTRING),
+    DeviceRemoved(HSTRING),
+    /// Sent when a device is added or changes state, so `devices_watch` can be updated.
+    /// If there's no current device by the time they settle, the default device is acquired.
+    DevicesChanged,
+    /// Sets the volume, after mapping it through the mapping set by [`AudioThreadCommand::SetVolumeMapping`].
+    /// NaN and infinities are ignored, here and in the other commands that set the volume.
+    SetVolume(f32),
+    /// Sets the volume like [`AudioThreadCommand::SetVolume`] and unmutes the device, for when the user moving
+    /// the volume means they want to hear it. Unlike `SetVolume`, the new state is sent to `volume_watch`.
+    SetVolumeAndUnmute(f32),
+    /// Sets the volume while ignoring the safety cap, for this single change only. The mapping still applies.
+    SetVolumeUnclamped(f32),
+    /// Sets the volume as a fraction of the cap, after mapping the cap back to the volume the app works with,
+    /// so 1.0 means the cap itself.
+    SetVolumeFractionOfMax(f32),
+    /// Sets the volume in decibels, clamped to the range of the device. The cap and the slew limit still apply.
+    ///
+    /// The exact dB value is set unless it could end up near or above the cap, or would have to be ramped up to.
+    /// Then it's converted to a scalar instead, so the level is only approximate, since every device has its own curve.
+    SetVolumeDb(f32),
+    /// Reads the `(min_db, max_db, increment_db)` volume range of the current device, or `None` if there is no device.
+    GetVolumeRange(oneshot::Sender<Option<(f32, f32, f32)>>),
+    /// Reads the volume of the current device in decibels, or `None` if there is no device or reading it failed.
+    GetVolumeDb(oneshot::Sender<Option<f32>>),
+    /// Moves the volume to `target` gradually over `duration_ms` along `curve`, after mapping it like [`AudioThreadCommand::SetVolume`].
+    /// A new fade replaces the current one, starting from wherever it got to, and any other volume change cancels it.
+    FadeVolume {
+        target: f32,
+        duration_ms: u32,
+        curve: FadeCurve,
+    },
+    /// Raises or lowers the mapped volume by the given amount, stopping at 0.0 and the cap.
+    /// Unlike the other volume commands, the new volume is sent to `volume_watch`.
+    AdjustVolume(f32),
+    /// Sets the volume like [`AudioThreadCommand::SetVolume`], but with a caller-provided event context instead of our own,
+    /// so other controllers can recognize their own changes. The change is only hidden from `volume_watch` if `context` is in the ignored contexts.
+    SetVolumeWithContext {
+        volume: f32,
+        context: GUID,
+    },
+    /// Replaces the event contexts whose changes are hidden from `volume_watch`, on top of our own.
+    SetIgnoredVolumeContexts(Vec<GUID>),
+    /// Mutes or unmutes the current device, without touching its volume. Likewise, none of the commands that set
+    /// the volume unmute the device, except for [`AudioThreadCommand::SetVolumeAndUnmute`].
+    SetMute(bool),
+    /// Flips the mute state of the current device, so callers don't have to read it first and race other changes.
+    /// Unlike [`AudioThreadCommand::SetMute`], the new state is sent to `volume_watch`.
+    ToggleMute,
+    /// Sets the left/right balance, from -1.0 (left) to 1.0 (right).
+    SetBalance(f32),
+    /// Sets the front/rear balance, from -1.0 (front) to 1.0 (rear).
+    /// Does nothing on devices without rear speakers.
+    SetFrontRearBalance(f32),
+    /// Reads the front/rear balance, or `None` if there is no device, or it doesn't have rear speakers.
+    GetFrontRearBalance(oneshot::Sender<Option<f32>>),
+    /// Sets the volume of a single channel of the current device, mapped like [`AudioThreadCommand::SetVolume`]
+    /// and stopping at the cap.
+    /// Channels the device doesn't have, and levels of NaN, are ignored.
+    SetChannelVolume {
+        channel: u32,
+        level: f32,
+    },
+    /// Applies a whole [`MixerState`] to the current device at once, and sends the result to `volume_watch` once.
+    /// Volumes are mapped and limited like the commands that set them one by one. A state that doesn't fit the device,
+    /// or has any value that would be ignored on its own, is rejected as a whole, leaving the device as it was.
+    ApplyMixerState(MixerState),
+    /// Reads the mapped volume of every channel of the current device, in channel order, or `None` if there is no device.
+    GetChannelVolumes(oneshot::Sender<Option<Vec<f32>>>),
+    /// Applies every volume change on the `source` device to the `target` device as well.
+    AddMirror {
+        source: HSTRING,
+        target: HSTRING,
+    },
+    RemoveMirror {
+        source: HSTRING,
+        target: HSTRING,
+    },
+    /// Sent by the callback of the current device when something else changes its volume.
+    VolumeChanged {
+        device_id: HSTRING,
+        context: GUID,
+        state: VolumeState,
+        balance: Option<f32>,
+    },
+    /// Sent by the callback of a mirrored device when its volume changes.
+    MirrorSourceChanged {
+        source: HSTRING,
+        volume: f32,
+    },
+    /// Reads the state of every active output device, without keeping any of them around.
+    GetAllDeviceStates(oneshot::Sender<Vec<DeviceState>>),
+    /// Releases the current device and ignores new defaults until [`AudioThreadCommand::Resume`].
+    /// Sent by [`AudioMonitor::stop`].
+    Pause,
+    /// Sent by [`AudioMonitor::start`].
+    Resume,
+    /// Stops the audio thread, releasing every device and saving the volume if it hasn't been yet.
+    /// Sent when the [`AudioMonitor`] is dropped, since the device callbacks keep senders of their own.
+    Shutdown,
+    /// Controls the given device instead of the default one, until [`AudioThreadCommand::FollowDefault`].
+    /// If the device is removed, there's no device until it's pinned again or the default is followed.
+    SetTargetDevice(HSTRING),
+    /// Goes back to following the default device after [`AudioThreadCommand::SetTargetDevice`].
+    FollowDefault,
+    /// Changes the cap on the volume the app sets, from 0.0 to 1.0. NaN is ignored.
+    /// Lowering the cap below the current volume turns the device down to it.
+    SetMaxVolume(f32),
+    /// Limits how fast the volume can be raised, in volume per second. Larger increases are ramped up to instead,
+    /// while decreases are always applied immediately. Applies to every volume command the frontend sends,
+    /// including single channels, and fades are stretched to fit. Only [`AudioThreadCommand::SetVolumeUnclamped`]
+    /// and [`AudioThreadCommand::SetVolumeWithContext`] skip it.
+    /// `None` (the default) disables the limit.
+    SetMaxVolumeSlewRate(Option<f32>),
+    /// Changes how the values of [`AudioThreadCommand::SetVolume`] and `volume_watch` map onto the device volume.
+    /// Mappings that would leave 0.0..=1.0 are rejected.
+    SetVolumeMapping {
+        gain: f32,
+        offset: f32,
+    },
+    /// Replaces the devices that are never acquired, even when they become the default.
+    /// The current device is kept when a blocked one becomes the default instead.
+    SetBlockedDevices(Vec<HSTRING>),
+    /// Reads the most recent volume changes made by other apps, oldest first.
+    GetVolumeChangeHistory(oneshot::Sender<Vec<ExternalVolumeChange>>),
+    /// Reads the current device again and sends its state, even if nothing changed, to wake up the receivers.
+    ResendState,
+    /// Reads the state of the current device, mapped like `volume_watch`, or `None` if there is no device.
+    /// Unlike `volume_watch`, this includes changes made through the app itself, and since commands are handled
+    /// in order, it reflects every command sent before it.
+    GetVolumeState(oneshot::Sender<Option<VolumeState>>),
+    /// Like [`AudioThreadCommand::GetVolumeState`], but for callers that block on the reply with a timeout,
+    /// like [`AudioMonitor::set_and_wait`]. Only replies with the volume.
+    ConfirmVolume(mpsc::Sender<Option<f32>>),
+    /// Mutes every active output device, not just the current one, and replies with how many were muted.
+    /// Devices that were already muted are left alone, and aren't counted.
+    MuteAllOutputs(oneshot::Sender<usize>),
+    /// Unmutes the devices muted by [`AudioThreadCommand::MuteAllOutputs`], and replies with how many were unmuted.
+    UnmuteAllOutputs(oneshot::Sender<usize>),
+    /// Lists every active output device, flagging the default one for the role the monitor follows.
+    ListOutputDevices(oneshot::Sender<Vec<OutputDeviceInfo>>),
+    /// Looks up the friendly name of a device, or `None` if it doesn't exist or doesn't have one.
+    /// Sent by [`AudioMonitor::device_name_for_id`].
+    DeviceNameForId {
+        device_id: HSTRING,
+        reply: oneshot::Sender<Option<String>>,
+    },
+    /// Looks up the ID of the first active device of the monitor's flow with the given friendly name.
+    /// Sent by [`AudioMonitor::device_id_for_name`].
+    DeviceIdForName {
+        name: String,
+        reply: oneshot::Sender<Option<HSTRING>>,
+    },
+    /// Reads the format the audio engine mixes in for the current device, or `None` if there is no device.
+    GetMixFormat(oneshot::Sender<Option<MixFormat>>),
+    /// Sets the volume of every session of the process with the given ID on the current device, from 0.0 to 1.0.
+    /// Session volumes are relative to the device volume, so the cap doesn't apply. NaN is ignored.
+    SetSessionVolume {
+        pid: u32,
+        level: f32,
+    },
+    /// Like [`AudioThreadCommand::SetSessionVolume`], but for every process with the given executable name,
+    /// like `Spotify.exe`, ignoring case.
+    SetSessionVolumeByName {
+        process_name: String,
+        level: f32,
+    },
+    /// Lists the sessions on the current device that haven't expired, or nothing if there is no device.
+    ListSessions(oneshot::Sender<Vec<SessionInfo>>),
+    /// Saves the volume of every [`AudioThreadCommand::SetVolume`] and the mute state of every
+    /// [`AudioThreadCommand::SetMute`] and [`AudioThreadCommand::ToggleMute`] to the given JSON file, once they
+    /// have settled, and restores both once there is a device. `None` (the default) stops saving them,
+    /// without removing the file.
+    SetPersistencePath(Option<PathBuf>),
+    /// Starts or stops reading the peak level of the current device into `peak_watch`. Disabled by default,
+    /// and the peak is reset to 0.0 when it's disabled.
+    SetPeakMetering(bool),
+    /// Loads the presets from the given JSON file, and saves them there whenever they change.
+    /// `None` (the default) keeps them in memory only, without forgetting the ones already loaded.
+    SetPresetsPath(Option<PathBuf>),
+    /// Stores the current volume under the given name, replacing any preset that already has it.
+    /// Does nothing if there is no device.
+    SavePreset(String),
+    /// Sets the volume stored under the given name, like [`AudioThreadCommand::SetVolume`] would.
+    /// Names without a preset are ignored.
+    RecallPreset(String),
+    /// Reads every preset, sorted by name.
+    ListPresets(oneshot::Sender<Vec<Preset>>),
+    /// Applies the saved volume and mute state again, once there is a device. Does nothing if there's no
+    /// persistence path, or nothing valid has been saved there, in which case the device keeps its own volume.
+    RestoreSaved,
+    /// Sent by the audio thread itself to restore the saved volume, mapped and limited like
+    /// [`AudioThreadCommand::SetVolume`], along with the mute state if one was saved.
+    /// Both are applied together, and the result is sent to `volume_watch` once.
+    RestoreVolume {
+        volume: f32,
+        muted: Option<bool>,
+    },
+}
+
+/// What's written to the file set by [`AudioThreadCommand::SetPersistencePath`].
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct SavedVolume {
+    volume: f32,
+    /// `None` until the app mutes or unmutes the device, so restoring doesn't touch a mute state it never set.
+    /// Files from before mute was saved don't have it either.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    muted: Option<bool>,
+}
+
+/// Everything [`AudioThreadCommand::ApplyMixerState`] sets, with volumes like [`AudioThreadCommand::SetVolume`] takes them.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MixerState {
+    pub volume: f32,
+    pub muted: bool,
+    /// The left/right balance, from -1.0 (left) to 1.0 (right), or `None` to leave it as it is.
+    /// Needs at least two channels.
+    pub balance: Option<f32>,
+    /// The volume of every channel, in channel order, or `None` to leave them as they are.
+    /// Has to have exactly as many as the device, and can't be combined with `balance`, since both set the channels.
+    pub channel_levels: Option<Vec<f32>>,
+}
+
+impl MixerState {
+    /// Describes what keeps the state from being applied to a device with `channel_count` channels, if anything.
+    fn check(&self, channel_count: u32) -> Result<(), String> {
+        if !self.volume.is_finite() {
+            return Err(format!("its volume is {}", self.volume));
+        }
+
+        if self.balance.is_some_and(f32::is_nan) {
+            return Err("its balance is NaN".to_string());
+        }
+
+        if self.balance.is_some() && channel_count < 2 {
+            return Err(format!(
+                "it sets a balance on a device with {channel_count} channel(s)"
+            ));
+        }
+
+        let Some(levels) = &self.channel_levels else {
+            return Ok(());
+        };
+
+        if self.balance.is_some() {
+            return Err("it sets both a balance and the channel levels".to_string());
+        }
+
+        if levels.len() != channel_count as usize {
+            return Err(format!(
+                "it has {} channel level(s) for a device with {channel_count} channel(s)",
+                levels.len()
+            ));
+        }
+
+        if levels.iter().any(|level| level.is_nan()) {
+            return Err("one of its channel levels is NaN".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+/// A volume saved under a name by [`AudioThreadCommand::SavePreset`].
+#[derive(Debug, Clone, Serialize)]
+pub struct Preset {
+    pub name: String,
+    /// Like the values of [`AudioThreadCommand::SetVolume`], before the mapping is applied.
+    pub volume: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceInfo {
+    pub id: String,
+    /// `None` if the device doesn't have a friendly name.
+    pub name: Option<String>,
+    /// Shared by all endpoints of the same physical device, like the outputs of a USB dock.
+    pub container_id: Option<String>,
+}
+
+/// A notification from `IMMNotificationClient`, for every device, flow and role.
+#[derive(Debug, Clone)]
+pub enum DeviceNotification {
+    Added(HSTRING),
+    Removed(HSTRING),
+    StateChanged {
+        device_id: HSTRING,
+        state: DEVICE_STATE,
+    },
+    /// `device_id` is `None` if there no longer is a default device for `flow` and `role`.
+    DefaultChanged {
+        flow: EDataFlow,
+        role: ERole,
+        device_id: Option<HSTRING>,
+    },
+    PropertyChanged {
+        device_id: HSTRING,
+        key: PROPERTYKEY,
+    },
+}
+
+/// The format the audio engine uses for a device in shared mode.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct MixFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bits_per_sample: u16,
+    /// Which speaker positions the channels map to, in the order of the set bits.
+    /// `None` if the format doesn't say, in which case the layout has to be guessed from the channel count.
+    pub channel_mask: Option<u32>,
+}
+
+/// A volume change on the current device that didn't come from us.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExternalVolumeChange {
+    /// The event context the other app passed along, which some apps always set to the same GUID.
+    pub context: String,
+    pub volume: f32,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+/// An active output device, for letting the user pick one.
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputDeviceInfo {
+    #[serde(flatten)]
+    pub info: DeviceInfo,
+    /// Whether this is the default device for the role the monitor follows.
+    pub is_default: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceState {
+    #[serde(flatten)]
+    pub info: DeviceInfo,
+    #[serde(flatten)]
+    pub volume: VolumeState,
+    /// Whether the volume is applied by the device itself. If not, Windows attenuates the signal before sending it,
+    /// which is common for Bluetooth headsets without absolute volume, and the headset's own volume is unaffected.
+    pub absolute_volume_supported: bool,
+    /// Some virtual devices report no channels, and can't actually have their volume controlled.
+    /// These are never followed as the default device.
+    pub supports_volume: bool,
+}
+
+/// A failure of the audio stack that isn't just a missing device. These are mostly transient,
+/// like during a driver reset or when a remote desktop session takes over the audio.
+#[derive(Debug, thiserror::Error)]
+pub enum AudioError {
+    #[error("failed to create device enumerator: {0}")]
+    CreateEnumerator(windows_core::Error),
+    #[error("failed to retrieve audio device: {0}")]
+    GetDevice(windows_core::Error),
+    #[error("failed to retrieve default audio output device: {0}")]
+    GetDefaultDevice(windows_core::Error),
+    #[error("failed to create audio endpoint volume object: {0}")]
+    ActivateVolume(windows_core::Error),
+    #[error("failed to register volume change callback: {0}")]
+    RegisterVolumeCallback(windows_core::Error),
+}
+
+impl AudioError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            AudioError::CreateEnumerator(_) => ErrorKind::DeviceEnumerator,
+            AudioError::GetDevice(_) | AudioError::GetDefaultDevice(_) => ErrorKind::DeviceLookup,
+            AudioError::ActivateVolume(_) | AudioError::RegisterVolumeCallback(_) => {
+                ErrorKind::DeviceActivation
+            }
+        }
+    }
+}
+
+/// What went wrong in an [`ErrorEvent`], so the UI can describe it in its own words.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorKind {
+    /// The audio stack couldn't be reached at all.
+    DeviceEnumerator,
+    /// A device couldn't be looked up, even though it might exist.
+    DeviceLookup,
+    /// A device was found, but its volume couldn't be controlled.
+    DeviceActivation,
+    /// Windows sent a device notification that couldn't be understood.
+    Notification,
+    /// The saved volume couldn't be read or written.
+    Persistence,
+    /// The current device stopped responding, usually because it was unplugged before Windows announced it.
+    DeviceLost,
+}
+
+/// A recoverable error hit by the audio thread or the device notifications, sent through [`AudioMonitor::errors`].
+/// It has already been logged by the time it's sent.
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorEvent {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+/// The COM concurrency model of the thread that creates an [`AudioMonitor`].
+///
+/// The audio thread and the notification callbacks always run in the multithreaded apartment,
+/// so this only affects the creating thread.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ComApartment {
+    /// Lets COM call into the monitor from any thread, so nothing else is required from the creating thread.
+    #[default]
+    MultiThreaded,
+    /// For embedding into a single-threaded apartment, like most UI threads.
+    ///
+    /// COM delivers calls into the apartment through its message queue, so the creating thread has to keep
+    /// pumping messages for as long as the monitor is alive, or unregistering the callbacks on drop can hang.
+    ApartmentThreaded,
+}
+
+/// How the volume moves over the course of an [`AudioThreadCommand::FadeVolume`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FadeCurve {
+    /// At the same pace all the way, which can sound abrupt at the ends.
+    #[default]
+    Linear,
+    /// Starts and ends slowly.
+    EaseInOut,
+    /// Starts slowly and speeds up towards the end.
+    Exponential,
+}
+
+impl FadeCurve {
+    /// Maps how far along the fade is in time, from 0.0 to 1.0, to how far along it is in volume.
+    fn ease(self, progress: f32) -> f32 {
+        match self {
+            FadeCurve::Linear => progress,
+            FadeCurve::EaseInOut => progress * progress * (3.0 - 2.0 * progress),
+            // Shifted and scaled to start at exactly 0.0 and end at exactly 1.0.
+            FadeCurve::Exponential => ((10.0 * progress).exp2() - 1.0) / 1023.0,
+        }
+    }
+
+    /// How much faster than a linear fade the curve moves at its steepest.
+    fn steepest_slope(self) -> f32 {
+        match self {
+            FadeCurve::Linear => 1.0,
+            FadeCurve::EaseInOut => 1.5,
+            FadeCurve::Exponential => 10.0 * std::f32::consts::LN_2 * 1024.0 / 1023.0,
+        }
+    }
+}
+
+/// Whether an [`AudioMonitor`] controls output devices or input devices, like microphones.
+///
+/// A capture monitor works the same way, so the commands and watches that mention output devices
+/// cover input devices for it instead. One process can run a monitor for each.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DataFlow {
+    #[default]
+    Render,
+    Capture,
+}
+
+impl DataFlow {
+    fn edataflow(self) -> EDataFlow {
+        match self {
+            DataFlow::Render => eRender,
+            DataFlow::Capture => eCapture,
+        }
+    }
+}
+
+/// Which of the default devices an [`AudioMonitor`] follows. Windows keeps one for each role,
+/// although most setups only set the first two apart, if at all.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeviceRole {
+    /// Games, system sounds and most other applications.
+    #[default]
+    Console,
+    /// Music and movies.
+    Multimedia,
+    /// Voice calls, which some setups route to a headset while everything else stays on the speakers.
+    Communications,
+}
+
+impl DeviceRole {
+    fn erole(self) -> ERole {
+        match self {
+            DeviceRole::Console => eConsole,
+            DeviceRole::Multimedia => eMultimedia,
+            DeviceRole::Communications => eCommunications,
+        }
+    }
+}
+
+/// How initializing COM went on the thread that created an [`AudioMonitor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComInitialization {
+    /// COM wasn't initialized on the thread before, and is uninitialized again when the monitor is dropped.
+    Initialized,
+    /// COM was already initialized with the same apartment model. Windows still counts our initialization,
+    /// so it's balanced with its own uninitialization when the monitor is dropped, and COM stays up for the other owner.
+    AlreadyInitialized,
+    /// COM was already initialized with a different apartment model by someone else. It's still usable,
+    /// but our initialization didn't count, so it's not uninitialized when the monitor is dropped.
+    ChangedMode,
+}
+
+/// Balances a call to `CoInitializeEx`, unless that call didn't count.
+#[derive(Debug)]
+struct CoInitializeGuard {
+    initialization: ComInitialization,
+    _not_send: NotSendMarker,
+}
+
+impl Drop for CoInitializeGuard {
+    fn drop(&mut self) {
+        if self.initialization == ComInitialization::ChangedMode {
+            return;
+        }
+
+        // SAFETY: Every other outcome of `initialize_com` means that `CoInitializeEx()` succeeded, and has to be balanced.
+        // Making it !Send means that it is always called on the same thread it was created on.
+        unsafe {
+            CoUninitialize();
+        }
+    }
+}
+
+fn initialize_com(apartment: ComApartment) -> CoInitializeGuard {
+    let model = match apartment {
+        ComApartment::MultiThreaded => COINIT_MULTITHREADED,
+        ComApartment::ApartmentThreaded => COINIT_APARTMENTTHREADED,
+    };
+
+    // SAFETY: `pvreserved` is None, and the combination of flags is valid.
+    let result = unsafe { CoInitializeEx(None, model | COINIT_DISABLE_OLE1DDE) };
+
+    // `S_FALSE` is a success code, so it has to be checked before `ok()`, which would treat it like `S_OK`.
+    let initialization = if result == S_OK {
+        ComInitialization::Initialized
+    } else if result == S_FALSE {
+        ComInitialization::AlreadyInitialized
+    } else if result == RPC_E_CHANGED_MODE {
+        // COM is still usable, just not with the model we asked for.
+        eprintln!(
+            "COM library already initialized with a different apartment model than {apartment:?}"
+        );
+        ComInitialization::ChangedMode
+    } else {
+        panic!("failed to initialize COM library, error code: {result:?}")
+    };
+
+    CoInitializeGuard {
+        initialization,
+        _not_send: PhantomData,
+    }
+}
+
+/// Logs the error, for callers that treat a failure the same as a missing device.
+fn log_audio_error<T>(result: Result<Option<T>, AudioError>) -> Option<T> {
+    result.unwrap_or_else(|e| {
+        eprintln!("{e}");
+        None
+    })
+}
+
+/// Logs the error, and sends it to whoever is subscribed to `errors`.
+fn report_error(errors: &broadcast::Sender<ErrorEvent>, kind: ErrorKind, message: String) {
+    eprintln!("{message}");
+
+    // This only fails if nobody is subscribed, and then there's nobody to miss it either.
+    let _ = errors.send(ErrorEvent { kind, message });
+}
+
+fn create_device_enumerator() -> Result<IMMDeviceEnumerator, AudioError> {
+    // SAFETY: We don't pass a pointer in `punkouter`, so it can't be invalid.
+    unsafe { CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL) }
+        .map_err(AudioError::CreateEnumerator)
+}
+
+fn get_device<ID: Param<PCWSTR>>(
+    device_enumerator: &IMMDeviceEnumerator,
+    id: ID,
+) -> Result<Option<IMMDevice>, AudioError> {
+    // SAFETY: `device_enumerator` is a valid reference.
+    match unsafe { device_enumerator.GetDevice(id) } {
+        Ok(device) => Ok(Some(device)),
+        Err(e) if e.code() == ERROR_NOT_FOUND.to_hresult() => {
+            eprintln!("no output devices found");
+            Ok(None)
+        }
+        Err(e) => Err(AudioError::GetDevice(e)),
+    }
+}
+
+fn get_default_device(
+    device_enumerator: &IMMDeviceEnumerator,
+    flow: DataFlow,
+    role: DeviceRole,
+) -> Result<Option<IMMDevice>, AudioError> {
+    // SAFETY: `device_enumerator` is a valid reference.
+    match unsafe { device_enumerator.GetDefaultAudioEndpoint(flow.edataflow(), role.erole()) } {
+        Ok(device) => Ok(Some(device)),
+        Err(e) if e.code() == ERROR_NOT_FOUND.to_hresult() => {
+            eprintln!("no {flow:?} devices found");
+            Ok(None)
+        }
+        Err(e) => Err(AudioError::GetDefaultDevice(e)),
+    }
+}
+
+fn get_device_id(device: &IMMDevice) -> HSTRING {
+    // SAFETY: `device` is a valid reference.
+    let id = unsafe { device.GetId() }.expect("should have enough memory");
+    // SAFETY: `id` contains a valid pointer.
+    unsafe { id.to_hstring() }.expect("should have enough memory")
+}
+
+fn get_device_info(device: &IMMDevice) -> DeviceInfo {
+    // SAFETY: `device` is a valid reference.
+    let property_store = unsafe { device.OpenPropertyStore(STGM_READ) }.ok();
+    let property = |key| {
+        property_store
+            .as_ref()
+            .and_then(|store| get_string_property(store, key))
+    };
+
+    DeviceInfo {
+        id: get_device_id(device).to_string(),
+        name: property(&PKEY_Device_FriendlyName),
+        container_id: property(&PKEY_DEVICE_CONTAINER_ID),
+    }
+}
+
+fn get_device_name(device: &IMMDevice) -> Option<String> {
+    // SAFETY: `device` is a valid reference.
+    let property_store = unsafe { device.OpenPropertyStore(STGM_READ) }.ok()?;
+
+    get_string_property(&property_store, &PKEY_Device_FriendlyName)
+}
+
+fn get_string_property(property_store: &IPropertyStore, key: &PROPERTYKEY) -> Option<String> {
+    // SAFETY: `property_store` is a valid reference.
+    let value = unsafe { property_store.GetValue(key) }.ok()?;
+
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Looks up the friendly name of a device, if it exists and has one.
+fn device_name_for_id(device_enumerator: &IMMDeviceEnumerator, id: &HSTRING) -> Option<String> {
+    // SAFETY: `device_enumerator` is a valid reference.
+    let device = unsafe { device_enumerator.GetDevice(id) }.ok()?;
+
+    get_device_name(&device)
+}
+
+/// Looks up the ID of the first active device of the given flow with the given friendly name.
+///
+/// Names aren't unique, so this is only meant for matching devices the user picked by name.
+fn device_id_for_name(
+    device_enumerator: &IMMDeviceEnumerator,
+    flow: DataFlow,
+    name: &str,
+) -> Option<HSTRING> {
+    get_active_devices(device_enumerator, flow)
+        .into_iter()
+        .find(|device| get_device_name(device).as_deref() == Some(name))
+        .map(|device| get_device_id(&device))
+}
+
+fn get_mix_format(device: &IMMDevice) -> windows_core::Result<MixFormat> {
+    // SAFETY: `device` is a valid reference, the generic is one of the allowed interfaces,
+    // and we don't pass a pointer in `pactivationparams`, so it can't be invalid.
+    // The client is released at the end of this function.
+    let audio_client = unsafe { device.Activate::<IAudioClient>(CLSCTX_ALL, None) }?;
+
+    // SAFETY: `audio_client` is a valid reference.
+    let mix_format = unsafe { audio_client.GetMixFormat() }?;
+
+    // SAFETY: `mix_format` was just returned by `GetMixFormat`, so it's valid.
+    // If the tag says it's extensible, the allocation is a `WAVEFORMATEXTENSIBLE`, which starts with a `WAVEFORMATEX`.
+    let format = unsafe {
+        MixFormat {
+            sample_rate: (*mix_format).nSamplesPerSec,
+            channels: (*mix_format).nChannels,
+            bits_per_sample: (*mix_format).wBitsPerSample,
+            channel_mask: ((*mix_format).wFormatTag == WAVE_FORMAT_EXTENSIBLE)
+                .then(|| (*mix_format.cast::<WAVEFORMATEXTENSIBLE>()).dwChannelMask),
+        }
+    };
+
+    // SAFETY: `mix_format` was allocated by `GetMixFormat`, and isn't used after this.
+    unsafe { CoTaskMemFree(Some(mix_format as *const _)) };
+
+    Ok(format)
+}
+
+/// Mutes or unmutes a device that isn't necessarily the current one, and returns whether it was muted before.
+fn set_device_mute(device: &IMMDevice, muted: bool) -> Option<bool> {
+    // SAFETY: `device` is a valid reference, the generic is one of the allowed interfaces,
+    // and we don't pass a pointer in `pactivationparams`, so it can't be invalid.
+    // The interface is released at the end of this function.
+    let volume_interface =
+        unsafe { device.Activate::<IAudioEndpointVolume>(CLSCTX_ALL, None) }.ok()?;
+
+    // SAFETY: `volume_interface` is a valid reference.
+    let was_muted = unsafe { volume_interface.GetMute() }.ok()?.as_bool();
+
+    // SAFETY: `volume_interface` is a valid reference.
+    if let Err(e) = unsafe { volume_interface.SetMute(muted, &LOCAL_VOLUME_CHANGE_GUID) } {
+        eprintln!(
+            "failed to change mute state of {}: {e}",
+            get_device_id(device)
+        );
+        return None;
+    }
+
+    Some(was_muted)
+}
+
+/// Reads the volume saved by [`save_volume`], or `None` if the file is missing or doesn't hold a valid volume.
+fn load_saved_volume(path: &Path, errors: &broadcast::Sender<ErrorEvent>) -> Option<SavedVolume> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            let message = format!("failed to read saved volume from {}: {e}", path.display());
+            report_error(errors, ErrorKind::Persistence, message);
+            return None;
+        }
+    };
+
+    match serde_json::from_str::<SavedVolume>(&contents) {
+        Ok(saved) if (0.0..=1.0).contains(&saved.volume) => Some(saved),
+        Ok(SavedVolume { volume, .. }) => {
+            let message =
+                format!("ignoring saved volume {volume}, since it's outside of 0.0..=1.0");
+            report_error(errors, ErrorKind::Persistence, message);
+            None
+        }
+        Err(e) => {
+            let message = format!("ignoring corrupt saved volume in {}: {e}", path.display());
+            report_error(errors, ErrorKind::Persistence, message);
+            None
+        }
+    }
+}
+
+fn save_volume(path: &Path, saved: SavedVolume, errors: &broadcast::Sender<ErrorEvent>) {
+    write_json(path, &saved, "volume", errors);
+}
+
+/// Reads the presets saved by [`save_presets`]. A missing file just means there aren't any yet.
+fn load_presets(path: &Path, errors: &broadcast::Sender<ErrorEvent>) -> BTreeMap<String, f32> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return BTreeMap::new(),
+        Err(e) => {
+            let message = format!("failed to read presets from {}: {e}", path.display());
+            report_error(errors, ErrorKind::Persistence, message);
+            return BTreeMap::new();
+        }
+    };
+
+    match serde_json::from_str(&contents) {
+        Ok(presets) => presets,
+        Err(e) => {
+            let message = format!("ignoring corrupt presets in {}: {e}", path.display());
+            report_error(errors, ErrorKind::Persistence, message);
+            BTreeMap::new()
+        }
+    }
+}
+
+fn save_presets(
+    path: &Path,
+    presets: &BTreeMap<String, f32>,
+    errors: &broadcast::Sender<ErrorEvent>,
+) {
+    write_json(path, presets, "presets", errors);
+}
+
+/// Writes `value` to `path` as JSON, reporting failures as `failed to save {what}`.
+fn write_json<T: Serialize>(
+    path: &Path,
+    value: &T,
+    what: &str,
+    errors: &broadcast::Sender<ErrorEvent>,
+) {
+    // The app data directory doesn't exist until something is saved in it.
+    if let Some(parent) = path.parent() {
+        if let Err(e) = std::fs::create_dir_all(parent) {
+            let message = format!("failed to create {}: {e}", parent.display());
+            report_error(errors, ErrorKind::Persistence, message);
+            return;
+        }
+    }
+
+    let contents =
+        serde_json::to_string(value).expect("maps and structs of floats should always serialize");
+
+    // Writing next to the file and renaming it over, so a crash halfway through can't leave it truncated.
+    let temp_path = path.with_extension("json.tmp");
+
+    if let Err(e) = std::fs::write(&temp_path, contents) {
+        let message = format!("failed to save {what} to {}: {e}", temp_path.display());
+        report_error(errors, ErrorKind::Persistence, message);
+        return;
+    }
+
+    if let Err(e) = std::fs::rename(&temp_path, path) {
+        let message = format!("failed to save {what} to {}: {e}", path.display());
+        report_error(errors, ErrorKind::Persistence, message);
+    }
+}
+
+/// Approximates the scalar volume of a dB value within the range of a device, treating it as an amplitude
+/// relative to the top of the range. The real scalar is tapered differently by every device, so this is only meant
+/// for deciding whether a dB value is safe to set under the cap, and as a fallback near it.
+fn db_to_scalar(db: f32, min_db: f32, max_db: f32) -> f32 {
+    if db <= min_db {
+        return 0.0;
+    }
+
+    10_f32.powf((db - max_db) / 20.0).clamp(0.0, 1.0)
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_millis() as u64)
+}
+
+/// Lists every active device of the given flow.
+///
+/// Devices can disappear while enumerating, and those are just skipped.
+fn get_active_devices(device_enumerator: &IMMDeviceEnumerator, flow: DataFlow) -> Vec<IMMDevice> {
+    // SAFETY: `device_enumerator` is a valid reference.
+    let devices = match unsafe {
+        device_enumerator.EnumAudioEndpoints(flow.edataflow(), DEVICE_STATE_ACTIVE)
+    } {
+        Ok(devices) => devices,
+        Err(e) => {
+            eprintln!("failed to enumerate {flow:?} devices: {e}");
+            return Vec::new();
+        }
+    };
+
+    // SAFETY: `devices` is a valid reference.
+    let count = unsafe { devices.GetCount() }.unwrap_or(0);
+
+    (0..count)
+        // SAFETY: `devices` is a valid reference.
+        .filter_map(|index| unsafe { devices.Item(index) }.ok())
+        .collect()
+}
+
+/// Lists every active device of the given flow, flagging the default one for `role`.
+///
+/// Devices can disappear while enumerating, and those are just skipped.
+fn list_output_devices(
+    device_enumerator: &IMMDeviceEnumerator,
+    flow: DataFlow,
+    role: DeviceRole,
+) -> Vec<OutputDeviceInfo> {
+    let default_id = log_audio_error(get_default_device(device_enumerator, flow, role))
+        .map(|device| get_device_id(&device));
+
+    get_active_devices(device_enumerator, flow)
+        .iter()
+        .map(|device| OutputDeviceInfo {
+            info: get_device_info(device),
+            is_default: default_id.as_ref() == Some(&get_device_id(device)),
+        })
+        .collect()
+}
+
+/// Reads the state of every active device of the given flow.
+///
+/// Devices can disappear while enumerating, and those are just skipped.
+fn get_all_device_states(
+    device_enumerator: &IMMDeviceEnumerator,
+    flow: DataFlow,
+) -> Vec<DeviceState> {
+    get_active_devices(device_enumerator, flow)
+        .iter()
+        .filter_map(get_device_state)
+        .collect()
+}
+
+fn get_device_state(device: &IMMDevice) -> Option<DeviceState> {
+    // SAFETY: `device` is a valid reference, the generic is one of the allowed interfaces,
+    // and we don't pass a pointer in `pactivationparams`, so it can't be invalid.
+    // The interface is released at the end of this function.
+    let volume_interface =
+        unsafe { device.Activate::<IAudioEndpointVolume>(CLSCTX_ALL, None) }.ok()?;
+
+    // SAFETY: `volume_interface` is a valid reference.
+    let (volume, muted, hardware_support, channel_count) = unsafe {
+        (
+            volume_interface.GetMasterVolumeLevelScalar().ok()?,
+            volume_interface.GetMute().ok()?,
+            volume_interface.QueryHardwareSupport().ok()?,
+            volume_interface.GetChannelCount().unwrap_or(0),
+        )
+    };
+
+    Some(DeviceState {
+        info: get_device_info(device),
+        volume: VolumeState {
+            volume,
+            muted: muted.as_bool(),
+        },
+        absolute_volume_supported: hardware_support & ENDPOINT_HARDWARE_SUPPORT_VOLUME != 0,
+        supports_volume: channel_count > 0,
+    })
+}
+
+/// Reads the default output device and its volume once, for when setting up an [`AudioMonitor`] would be overkill.
+///
+/// COM is initialized (and uninitialized) for just this call if the calling thread hasn't done so already,
+/// and nothing is shared with any `AudioMonitor` in the same process.
+pub fn current_default_volume() -> Option<(DeviceInfo, VolumeState)> {
+    let _coinitialize_guard = initialize_com(ComApartment::MultiThreaded);
+
+    let device_enumerator = create_device_enumerator().ok()?;
+    let device = log_audio_error(get_default_device(
+        &device_enumerator,
+        DataFlow::Render,
+        DeviceRole::default(),
+    ))?;
+    let state = get_device_state(&device)?;
+
+    Some((state.info, state.volume))
+}
+
+#[derive(Debug, Clone)]
+struct VolumeWatchers {
+    volume: watch::Sender<Option<VolumeState>>,
+    /// Bumped on every volume update, so receivers can tell how many values they missed.
+    volume_sequence: Arc<AtomicU64>,
+    /// The mapping is applied to every volume before it's sent to `volume`.
+    limits: watch::Sender<VolumeLimits>,
+    /// `None` if there is no device, or if it has fewer than two channels.
+    balance: watch::Sender<Option<f32>>,
+    device_name: watch::Sender<Option<String>>,
+    device_id: watch::Sender<Option<String>>,
+    /// 0.0 if there is no device, or metering is disabled.
+    peak: watch::Sender<f32>,
+    errors: broadcast::Sender<ErrorEvent>,
+    #[cfg(feature = "loopback-meter")]
+    output_level: watch::Sender<Option<OutputLevel>>,
+    devices: watch::Sender<Vec<DeviceState>>,
+}
+
+impl VolumeWatchers {
+    fn mapping(&self) -> VolumeMapping {
+        self.limits.borrow().mapping
+    }
+
+    fn max_volume(&self) -> f32 {
+        self.limits.borrow().max
+    }
+
+    /// Like [`log_audio_error`], but also sends the error to `errors`.
+    fn report_audio_error<T>(&self, result: Result<Option<T>, AudioError>) -> Option<T> {
+        result.unwrap_or_else(|e| {
+            report_error(&self.errors, e.kind(), e.to_string());
+            None
+        })
+    }
+
+    /// Sends the state of the new current device, or that there is none.
+    fn send_current_device(&self, device: Option<&AudioOutputDevice>) {
+        // A device that can't be read anymore is dropped by the next change to it, until then it just has no state.
+        let state = device.and_then(|device| {
+            device
+                .state()
+                .inspect_err(|e| eprintln!("failed to read volume state: {e}"))
+                .ok()
+        });
+        let balance = device.and_then(|device| {
+            device
+                .balance()
+                .inspect_err(|e| eprintln!("failed to read balance: {e}"))
+                .ok()
+                .flatten()
+        });
+        let device_name = device.map(|device| device.name.clone());
+        let device_id = device.map(|device| device.device_id.to_string());
+
+        self.send_volume(state);
+        self.balance.send_replace(balance);
+        self.device_name.send_replace(device_name);
+        // Sent after the name, so whoever reacts to a new ID sees the name of the same device.
+        self.device_id.send_replace(device_id);
+        self.send_peak(0.0);
+    }
+
+    /// Only wakes up the receivers if the peak changed, since it's polled even when nothing is playing.
+    fn send_peak(&self, peak: f32) {
+        self.peak
+            .send_if_modified(|current| std::mem::replace(current, peak) != peak);
+    }
+
+    /// Maps the volume of a device through the current [`VolumeMapping`], into what the app works with.
+    fn app_state(&self, state: VolumeState) -> VolumeState {
+        VolumeState {
+            volume: self.mapping().app_volume(state.volume),
+            ..state
+        }
+    }
+
+    /// Sends the volume state of the device, with the volume mapped through the current [`VolumeMapping`].
+    fn send_volume(&self, state: Option<VolumeState>) {
+        let state = state.map(|state| self.app_state(state));
+
+        if self.volume.is_closed() {
+            eprintln!("failed to send volume {state:?}, nobody is listening");
+        }
+
+        // Bumping the sequence while the value is locked means a receiver reading it under `borrow()` sees the matching count.
+        self.volume.send_modify(|current| {
+            *current = state;
+            self.volume_sequence.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+}
+
+/// Everything needed to talk to an audio thread started by [`spawn_audio_thread`].
+struct AudioThreadHandle {
+    command_sender: mpsc::Sender<AudioThreadCommand>,
+    thread: JoinHandle<()>,
+    volume_watch: watch::Receiver<Option<VolumeState>>,
+    volume_sequence: Arc<AtomicU64>,
+    heartbeat: Arc<AtomicU64>,
+    balance_watch: watch::Receiver<Option<f32>>,
+    device_name_watch: watch::Receiver<Option<String>>,
+    device_id_watch: watch::Receiver<Option<String>>,
+    peak_watch: watch::Receiver<f32>,
+    errors: broadcast::Sender<ErrorEvent>,
+    #[cfg(feature = "loopback-meter")]
+    output_level_watch: watch::Receiver<Option<OutputLevel>>,
+    devices_watch: watch::Receiver<Vec<DeviceState>>,
+    limits_watch: watch::Receiver<VolumeLimits>,
+}
+
+/// Starts an audio thread, which runs until it's sent [`AudioThreadCommand::Shutdown`].
+///
+/// The thread initializes COM for itself and creates its own device enumerator, so it works no matter how COM
+/// was (or wasn't) initialized on the calling thread. No COM object crosses the thread boundary, only the
+/// channels in the returned handle, which keeps it safe to call from any thread.
+fn spawn_audio_thread(flow: DataFlow, role: DeviceRole, limits: VolumeLimits) -> AudioThreadHandle {
+    let (command_tx, command_rx) = mpsc::channel::<AudioThreadCommand>();
+    let (watch_tx, watch_rx) = watch::channel(None);
+    let (balance_tx, balance_rx) = watch::channel(None);
+    let (device_name_tx, device_name_rx) = watch::channel(None);
+    let (device_id_tx, device_id_rx) = watch::channel(None);
+    let (peak_tx, peak_rx) = watch::channel(0.0);
+    let (errors, _) = broadcast::channel(ERROR_EVENT_CAPACITY);
+    let (devices_tx, devices_rx) = watch::channel(Vec::new());
+    let (limits_tx, limits_rx) = watch::channel(limits);
+    #[cfg(feature = "loopback-meter")]
+    let (output_level_tx, output_level_rx) = watch::channel(None);
+
+    let volume_sequence = Arc::new(AtomicU64::new(0));
+
+    let watchers = VolumeWatchers {
+        volume: watch_tx,
+        volume_sequence: volume_sequence.clone(),
+        limits: limits_tx,
+        balance: balance_tx,
+        device_name: device_name_tx,
+        device_id: device_id_tx,
+        peak: peak_tx,
+        errors: errors.clone(),
+        #[cfg(feature = "loopback-meter")]
+        output_level: output_level_tx,
+        devices: devices_tx,
+    };
+
+    let heartbeat = Arc::new(AtomicU64::new(0));
+
+    let thread = std::thread::spawn({
+        let command_tx = command_tx.clone();
+        let heartbeat = heartbeat.clone();
+        move || AudioMonitor::audio_thread(command_rx, command_tx, watchers, heartbeat, flow, role)
+    });
+
+    AudioThreadHandle {
+        command_sender: command_tx,
+        thread,
+        volume_watch: watch_rx,
+        volume_sequence,
+        heartbeat,
+        balance_watch: balance_rx,
+        device_name_watch: device_name_rx,
+        device_id_watch: device_id_rx,
+        peak_watch: peak_rx,
+        errors,
+        #[cfg(feature = "loopback-meter")]
+        output_level_watch: output_level_rx,
+        devices_watch: devices_rx,
+        limits_watch: limits_rx,
+    }
+}
+
+#[derive(Debug)]
+pub struct AudioMonitor {
+    /// `None` if there is no device.
+    pub volume_watch: watch::Receiver<Option<VolumeState>>,
+    /// How many times `volume_watch` has been updated. Only meant for diagnostics.
+    pub volume_sequence: Arc<AtomicU64>,
+    /// When the audio thread last started handling a command, in milliseconds since the Unix epoch.
+    /// Updated at least every second, unless the thread is stuck.
+    pub heartbeat: Arc<AtomicU64>,
+    pub balance_watch: watch::Receiver<Option<f32>>,
+    /// The friendly name of the current device, or its ID if it doesn't have one. `None` if there is no device.
+    pub device_name_watch: watch::Receiver<Option<String>>,
+    /// The ID of the current device, as listed by [`AudioMonitor::list_output_devices`]. `None` if there is no device.
+    pub device_id_watch: watch::Receiver<Option<String>>,
+    /// The peak level of what the current device is playing, from 0.0 to 1.0.
+    /// Only updated while [`AudioThreadCommand::SetPeakMetering`] is enabled.
+    pub peak_watch: watch::Receiver<f32>,
+    #[cfg(feature = "loopback-meter")]
+    pub output_level_watch: watch::Receiver<Option<OutputLevel>>,
+    /// Every active output device, updated shortly after devices are added, removed, enabled or disabled.
+    pub devices_watch: watch::Receiver<Vec<DeviceState>>,
+    /// Updated whenever the limits are changed through their commands.
+    pub limits_watch: watch::Receiver<VolumeLimits>,
+    /// Every device notification, unlike the commands, which only cover the default render device.
+    /// Call `subscribe()` to start receiving them.
+    pub device_notifications: broadcast::Sender<DeviceNotification>,
+    /// Recoverable errors, which are logged either way. Call `subscribe()` to start receiving them.
+    pub errors: broadcast::Sender<ErrorEvent>,
+    pub command_sender: mpsc::Sender<AudioThreadCommand>,
+    /// `None` once it has been joined, which only happens on drop.
+    audio_thread: Option<JoinHandle<()>>,
+    coinitialize_guard: CoInitializeGuard,
+    device_enumerator: IMMDeviceEnumerator,
+    device_event_notif_client: IMMNotificationClient,
+    flow: DataFlow,
+    role: DeviceRole,
+    monitoring: bool,
+}
+
+impl AudioMonitor {
+    /// Creates a monitor that follows the default device of `flow` for `role`.
+    pub fn new(apartment: ComApartment, flow: DataFlow, role: DeviceRole) -> Self {
+        Self::with_options(AudioMonitorBuilder {
+            apartment,
+            flow,
+            role,
+            ..AudioMonitorBuilder::default()
+        })
+    }
+
+    /// Starts configuring a monitor, which [`AudioMonitorBuilder::build`] then creates.
+    /// Anything that isn't set keeps the same default as [`AudioMonitor::default`].
+    pub fn builder() -> AudioMonitorBuilder {
+        AudioMonitorBuilder::default()
+    }
+
+    fn with_options(options: AudioMonitorBuilder) -> Self {
+        let AudioMonitorBuilder {
+            apartment,
+            flow,
+            role,
+            max_volume,
+        } = options;
+
+        let coinitialize_guard = initialize_com(apartment);
+
+        let AudioThreadHandle {
+            command_sender: command_tx,
+            thread,
+            volume_watch,
+            volume_sequence,
+            heartbeat,
+            balance_watch,
+            device_name_watch,
+            device_id_watch,
+            peak_watch,
+            errors,
+            #[cfg(feature = "loopback-meter")]
+            output_level_watch,
+            devices_watch,
+            limits_watch,
+        } = spawn_audio_thread(
+            flow,
+            role,
+            VolumeLimits {
+                max: max_volume,
+                ..VolumeLimits::default()
+            },
+        );
+
+        let device_enumerator = create_device_enumerator().expect("all parameters should be valid");
+
+        let (device_notifications, _) = broadcast::channel(DEVICE_NOTIFICATION_CAPACITY);
+
+        let device_event_notif_client = MMNotificationClient {
+            default_device_notifier: command_tx.clone(),
+            notifications: device_notifications.clone(),
+            errors: errors.clone(),
+            flow: flow.edataflow(),
+            role: role.erole(),
+        }
+        .into();
+
+        let mut monitor = Self {
+            coinitialize_guard,
+            command_sender: command_tx,
+            audio_thread: Some(thread),
+            device_enumerator,
+            device_event_notif_client,
+            volume_watch,
+            volume_sequence,
+            heartbeat,
+            balance_watch,
+            device_name_watch,
+            device_id_watch,
+            peak_watch,
+            #[cfg(feature = "loopback-meter")]
+            output_level_watch,
+            devices_watch,
+            limits_watch,
+            device_notifications,
+            errors,
+            flow,
+            role,
+            monitoring: false,
+        };
+
+        monitor.start();
+        monitor
+    }
+
+    /// Starts following the default device again after [`Self::stop`].
+    /// Does nothing if the monitor is already running, or the audio thread has stopped.
+    pub fn start(&mut self) {
+        if self.monitoring {
+            return;
+        }
+
+        // The audio thread gives up if the audio stack stays unavailable, and then there's nothing to monitor for.
+        if let Err(e) = self.command_sender.send(AudioThreadCommand::Resume) {
+            eprintln!("failed to resume audio thread: {e}");
+            return;
+        }
+
+        let device = log_audio_error(get_default_device(
+            &self.device_enumerator,
+            self.flow,
+            self.role,
+        ));
+
+        if let Some(device_id) = device.as_ref().map(get_device_id) {
+            if let Err(e) = self
+                .command_sender
+                .send(AudioThreadCommand::NewDefault(device_id))
+            {
+                eprintln!("failed to send default device to audio thread: {e}");
+                return;
+            }
+        }
+
+        // SAFETY: `device_enumerator` and `device_event_notif_client` are valid references.
+        unsafe {
+            self.device_enumerator
+                .RegisterEndpointNotificationCallback(&self.device_event_notif_client)
+        }
+        .expect("all parameters should be valid");
+
+        self.monitoring = true;
+    }
+
+    /// How initializing COM went on the thread that created the monitor, which decides whether dropping it uninitializes COM.
+    pub fn com_initialization(&self) -> ComInitialization {
+        self.coinitialize_guard.initialization
+    }
+
+    /// Lists every active device of the monitor's flow, flagging the default one for the role it follows.
+    pub fn list_output_devices(&self) -> Vec<OutputDeviceInfo> {
+        list_output_devices(&self.device_enumerator, self.flow, self.role)
+    }
+
+    /// Looks up the friendly name of a device on the audio thread. The reply is `None` if the device doesn't exist
+    /// or doesn't have a name, and receiving it fails if the audio thread isn't running.
+    pub fn device_name_for_id(&self, device_id: HSTRING) -> oneshot::Receiver<Option<String>> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+
+        // The reply sender is dropped along with a command that couldn't be sent, which the receiver reports.
+        if let Err(e) = self
+            .command_sender
+            .send(AudioThreadCommand::DeviceNameForId {
+                device_id,
+                reply: reply_tx,
+            })
+        {
+            eprintln!("failed to send device name request: {e}");
+        }
+
+        reply_rx
+    }
+
+    /// Looks up the ID of the first active device of the monitor's flow with the given friendly name, on the audio thread.
+    ///
+    /// Names aren't unique, so this is only meant for matching devices the user picked by name.
+    /// The reply is `None` if no device has the name, and receiving it fails if the audio thread isn't running.
+    pub fn device_id_for_name(&self, name: String) -> oneshot::Receiver<Option<HSTRING>> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+
+        // The reply sender is dropped along with a command that couldn't be sent, which the receiver reports.
+        if let Err(e) = self
+            .command_sender
+            .send(AudioThreadCommand::DeviceIdForName {
+                name,
+                reply: reply_tx,
+            })
+        {
+            eprintln!("failed to send device ID request: {e}");
+        }
+
+        reply_rx
+    }
+
+    /// Sets the volume like [`AudioThreadCommand::SetVolume`], and blocks until the audio thread has applied it.
+    ///
+    /// Returns the volume the device actually ended up at, mapped like `volume_watch`, since drivers can round
+    /// or clamp differently than expected. The cap still applies, and with a slew limit, this is where the ramp starts.
+    ///
+    /// Right after the monitor is created, the first device is still settling, so this fails with
+    /// [`ConfirmVolumeError::NoDevice`] until `device_id_watch` is `Some`.
+    pub fn set_and_wait(&self, volume: f32, timeout: Duration) -> Result<f32, ConfirmVolumeError> {
+        let (reply_tx, reply_rx) = mpsc::channel();
+
+        // Commands are handled in order, so the reply is only sent once the volume has been set.
+        self.command_sender
+            .send(AudioThreadCommand::SetVolume(volume))
+            .and_then(|()| {
+                self.command_sender
+                    .send(AudioThreadCommand::ConfirmVolume(reply_tx))
+            })
+            .map_err(|_| ConfirmVolumeError::Disconnected)?;
+
+        match reply_rx.recv_timeout(timeout) {
+            Ok(Some(volume)) => Ok(volume),
+            Ok(None) => Err(ConfirmVolumeError::NoDevice),
+            Err(RecvTimeoutError::Timeout) => Err(ConfirmVolumeError::TimedOut(timeout)),
+            Err(RecvTimeoutError::Disconnected) => Err(ConfirmVolumeError::Disconnected),
+        }
+    }
+
+    /// Sets the volume and unmutes the device, like [`AudioThreadCommand::SetVolumeAndUnmute`].
+    /// For the common case of the user moving a volume slider while the device is muted.
+    pub fn set_volume_and_unmute(&self, volume: f32) {
+        if let Err(e) = self
+            .command_sender
+            .send(AudioThreadCommand::SetVolumeAndUnmute(volume))
+        {
+            eprintln!("failed to send volume to audio thread: {e}");
+        }
+    }
+
+    /// Stops listening for device changes and releases the current device, without shutting down the audio thread.
+    /// Mirrors keep running, since they were set up explicitly. Does nothing if the monitor is already stopped.
+    pub fn stop(&mut self) {
+        if !self.monitoring {
+            return;
+        }
+
+        // SAFETY: `self.device_enumerator` is a valid reference and
+        // `self.device_event_notif_client` is the same interface originally registered.
+        unsafe {
+            self.device_enumerator
+                .UnregisterEndpointNotificationCallback(&self.device_event_notif_client)
+        }
+        .expect("all parameters should be valid");
+
+        if let Err(e) = self.command_sender.send(AudioThreadCommand::Pause) {
+            eprintln!("failed to pause audio thread: {e}");
+        }
+
+        self.monitoring = false;
+    }
+
+    // `command_sender` is only used to let the callbacks of mirrored devices report back to this thread,
+    // and to restore the saved volume and presets like any other `SetVolume`.
+    fn audio_thread(
+        commands: mpsc::Receiver<AudioThreadCommand>,
+        command_sender: mpsc::Sender<AudioThreadCommand>,
+        watchers: VolumeWatchers,
+        heartbeat: Arc<AtomicU64>,
+        flow: DataFlow,
+        role: DeviceRole,
+    ) {
+        // Don't rely on the creating thread having set up the multithreaded apartment, since it might be single-threaded.
+        // This is a fresh thread, so COM is always initialized by us here, and uninitialized when the thread exits.
+        let _coinitialize_guard = initialize_com(ComApartment::MultiThreaded);
+
+        // The audio stack can be briefly unavailable, for example while the audio service restarts.
+        let mut attempts = 1;
+        let device_enumerator = loop {
+            match create_device_enumerator() {
+                Ok(device_enumerator) => break device_enumerator,
+                Err(e) if attempts < DEVICE_ENUMERATOR_ATTEMPTS => {
+                    eprintln!("{e}, retrying in {DEVICE_ENUMERATOR_RETRY_DELAY:?}");
+                    attempts += 1;
+                    std::thread::sleep(DEVICE_ENUMERATOR_RETRY_DELAY);
+                }
+                Err(e) => {
+                    let message = format!("{e}, giving up on the audio thread");
+                    report_error(&watchers.errors, e.kind(), message);
+                    return;
+                }
+            }
+        };
+
+        let mut current_device = None;
+        let mut mirrors: Vec<Mirror> = Vec::new();
+        let mut paused = false;
+        let mut pending_default: Option<(HSTRING, Instant)> = None;
+        // Set by `SetTargetDevice`, and takes precedence over the default device.
+        let mut pinned_device: Option<HSTRING> = None;
+        let mut blocked_devices: Vec<HSTRING> = Vec::new();
+        // Devices muted by `MuteAllOutputs`, so devices the user muted themselves stay muted on `UnmuteAllOutputs`.
+        let mut muted_devices: Vec<HSTRING> = Vec::new();
+        // Event contexts of changes that shouldn't be reported, besides `LOCAL_VOLUME_CHANGE_GUID`.
+        let mut ignored_contexts: Vec<GUID> = Vec::new();
+        let mut change_history: VecDeque<ExternalVolumeChange> = VecDeque::new();
+        // Enumerate once at startup, so `devices_watch` isn't empty until something changes.
+        let mut pending_device_list = Some(Instant::now());
+        let mut max_slew_rate: Option<f32> = None;
+        // The volume being ramped up to, and when to take the next step towards it.
+        let mut slew_target: Option<(f32, Instant)> = None;
+        // The same for single channels, which are all stepped together.
+        let mut channel_slew_targets: BTreeMap<u32, f32> = BTreeMap::new();
+        let mut next_channel_slew_step = Instant::now();
+        let mut fade: Option<Fade> = None;
+        let mut persistence_path: Option<PathBuf> = None;
+        // Set until the saved volume has been applied to a device.
+        let mut pending_restore = false;
+        // What the persistence file holds, or will once `pending_save` is due.
+        let mut saved_volume: Option<SavedVolume> = None;
+        // When to save `saved_volume`, unless it changes again.
+        let mut pending_save: Option<Instant> = None;
+        let mut presets_path: Option<PathBuf> = None;
+        let mut presets: BTreeMap<String, f32> = BTreeMap::new();
+        let mut peak_metering = false;
+        let mut next_peak_poll = Instant::now();
+
+        loop {
+            heartbeat.store(unix_millis(), Ordering::Relaxed);
+
+            // Handle whatever is due first, so a steady stream of commands can't hold it back.
+            let now = Instant::now();
+
+            if let Some((device_id, _)) = pending_default.take_if(|(_, deadline)| *deadline <= now)
+            {
+                Self::switch_device(
+                    &mut current_device,
+                    device_id,
+                    &device_enumerator,
+                    &watchers,
+                    &command_sender,
+                    flow,
+                );
+            }
+
+            if pending_device_list
+                .take_if(|deadline| *deadline <= now)
+                .is_some()
+            {
+                watchers
+                    .devices
+                    .send_replace(get_all_device_states(&device_enumerator, flow));
+
+                // A device coming back after all of them were gone (like plugging a USB DAC back in) doesn't always
+                // announce a new default, so look for one ourselves.
+                if current_device.is_none() && !paused && pinned_device.is_none() {
+                    let default_device = watchers
+                        .report_audio_error(get_default_device(&device_enumerator, flow, role))
+                        .map(|device| get_device_id(&device))
+                        .filter(|device_id| !blocked_devices.contains(device_id));
+
+                    if let Some(device_id) = default_device {
+                        Self::switch_device(
+                            &mut current_device,
+                            device_id,
+                            &device_enumerator,
+                            &watchers,
+                            &command_sender,
+                            flow,
+                        );
+                    }
+                }
+            }
+
+            // Polling stops while there's nothing to read from, instead of waking up just to find that out.
+            let metering =
+                peak_metering && current_device.as_ref().is_some_and(|d| d.meter.is_some());
+
+            if metering && next_peak_poll <= now {
+                if let Some(peak) = current_device.as_ref().and_then(AudioOutputDevice::peak) {
+                    watchers.send_peak(peak);
+                }
+
+                next_peak_poll = now + PEAK_POLL_INTERVAL;
+            }
+
+            // Restoring goes through the queue, so the mapping, cap and slew limit apply just like to any other change.
+            if pending_restore && current_device.is_some() {
+                pending_restore = false;
+
+                if let Some(SavedVolume { volume, muted }) = saved_volume {
+                    command_sender
+                        .send(AudioThreadCommand::RestoreVolume { volume, muted })
+                        .expect("this thread owns the receiver");
+                }
+            }
+
+            if let Some((target, _)) = slew_target.take_if(|(_, next_step)| *next_step <= now) {
+                if let Some(rate) = max_slew_rate {
+                    let volume = Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        |device| {
+                            let volume = (device.volume()?
+                                + rate * SLEW_STEP_INTERVAL.as_secs_f32())
+                            .min(target);
+                            device.set_volume(volume, &LOCAL_VOLUME_CHANGE_GUID)?;
+                            Ok(volume)
+                        },
+                    );
+
+                    if volume.is_some_and(|volume| volume < target) {
+                        slew_target = Some((target, now + SLEW_STEP_INTERVAL));
+                    }
+                }
+            }
+
+            if !channel_slew_targets.is_empty() && next_channel_slew_step <= now {
+                let targets = std::mem::take(&mut channel_slew_targets);
+
+                if let Some(rate) = max_slew_rate {
+                    // Only the channels that haven't reached their target yet are kept.
+                    channel_slew_targets = Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        |device| {
+                            let levels = device.channel_levels()?;
+                            let mut remaining = BTreeMap::new();
+
+                            for (channel, target) in targets {
+                                let Some(&level) = levels.get(channel as usize) else {
+                                    continue;
+                                };
+
+                                let level =
+                                    (level + rate * SLEW_STEP_INTERVAL.as_secs_f32()).min(target);
+                                device.set_channel_level(channel, level)?;
+
+                                if level < target {
+                                    remaining.insert(channel, target);
+                                }
+                            }
+
+                            Ok(remaining)
+                        },
+                    )
+                    .unwrap_or_default();
+                }
+
+                next_channel_slew_step = now + SLEW_STEP_INTERVAL;
+            }
+
+            if let Some(step) = fade.take_if(|fade| fade.next_step <= now) {
+                let stepped = Self::with_current_device(
+                    &mut current_device,
+                    &mut pending_device_list,
+                    &watchers,
+                    |device| device.set_volume(step.volume_at(now), &LOCAL_VOLUME_CHANGE_GUID),
+                );
+
+                if stepped.is_some() && !step.is_done(now) {
+                    fade = Some(Fade {
+                        next_step: now + FADE_STEP_INTERVAL,
+                        ..step
+                    });
+                }
+            }
+
+            if pending_save.take_if(|deadline| *deadline <= now).is_some() {
+                if let (Some(saved), Some(path)) = (saved_volume, persistence_path.as_deref()) {
+                    save_volume(path, saved, &watchers.errors);
+                }
+            }
+
+            let deadline = pending_default
+                .as_ref()
+                .map(|(_, deadline)| *deadline)
+                .into_iter()
+                .chain(pending_device_list)
+                .chain(pending_save)
+                .chain(slew_target.map(|(_, next_step)| next_step))
+                .chain((!channel_slew_targets.is_empty()).then_some(next_channel_slew_step))
+                .chain(fade.map(|fade| fade.next_step))
+                .chain(metering.then_some(next_peak_poll))
+                .min();
+
+            let timeout = deadline.map_or(HEARTBEAT_INTERVAL, |deadline| {
+                deadline
+                    .saturating_duration_since(Instant::now())
+                    .min(HEARTBEAT_INTERVAL)
+            });
+
+            let command = match commands.recv_timeout(timeout) {
+                Ok(command) => command,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+
+            match command {
+                AudioThreadCommand::NewDefault(curr_device) => {
+                    if paused || pinned_device.is_some() {
+                        continue;
+                    }
+
+                    if blocked_devices.contains(&curr_device) {
+                        eprintln!("ignoring new default device {curr_device}, since it's blocked");
+                        pending_default = None;
+                        continue;
+                    }
+
+                    // Windows tends to announce a new default several times in a row (especially during startup),
+                    // so wait for it to settle instead of acquiring each one.
+                    pending_default =
+                        Some((curr_device, Instant::now() + DEFAULT_DEVICE_SETTLE_DELAY));
+                }
+                AudioThreadCommand::DevicesChanged => {
+                    // Devices tend to change in bursts, like when a headset connects, so wait for them to settle.
+                    pending_device_list = Some(Instant::now() + DEVICE_LIST_SETTLE_DELAY);
+                }
+                AudioThreadCommand::DeviceRemoved(removed_device) => {
+                    pending_device_list = Some(Instant::now() + DEVICE_LIST_SETTLE_DELAY);
+
+                    if pending_default
+                        .as_ref()
+                        .is_some_and(|(pending, _)| *pending == removed_device)
+                    {
+                        pending_default = None;
+                    }
+
+                    // Unregister callbacks if the removed device is the one we're using.
+                    if current_device
+                        .as_ref()
+                        .is_some_and(|curr| curr.device_id == removed_device)
+                    {
+                        current_device = None;
+
+                        watchers.send_current_device(None);
+                    }
+
+                    // Mirrors can't outlive their devices.
+                    mirrors.retain_mut(|m| {
+                        m.targets.retain(|t| t.device_id != removed_device);
+                        m.source.device_id != removed_device && !m.targets.is_empty()
+                    });
+                }
+                AudioThreadCommand::SetVolume(volume) => {
+                    // Clamping keeps NaN as it is, and it would be saved before the device rejects it.
+                    if !volume.is_finite() {
+                        eprintln!("ignoring a volume of {volume}");
+                        continue;
+                    }
+
+                    let volume = volume.clamp(0.0, 1.0);
+
+                    if persistence_path.is_some() {
+                        // Only the level changed, so a saved mute state stays as it is.
+                        let muted = saved_volume.and_then(|saved| saved.muted);
+                        saved_volume = Some(SavedVolume { volume, muted });
+                        pending_save = Some(Instant::now() + SAVE_DELAY);
+                    }
+
+                    let volume = watchers.mapping().device_volume(volume);
+
+                    // Don't accidentally blow up my ears when testing this.
+                    let volume = volume.clamp(0.0, watchers.max_volume());
+
+                    if current_device.is_none() {
+                        continue;
+                    }
+
+                    fade = None;
+
+                    Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        |device| {
+                            Self::set_volume_limited(
+                                device,
+                                volume,
+                                max_slew_rate,
+                                &mut slew_target,
+                            )
+                        },
+                    );
+                }
+                AudioThreadCommand::SetVolumeAndUnmute(volume) => {
+                    if !volume.is_finite() {
+                        eprintln!("ignoring a volume of {volume}");
+                        continue;
+                    }
+
+                    let volume = volume.clamp(0.0, 1.0);
+
+                    if persistence_path.is_some() {
+                        saved_volume = Some(SavedVolume {
+                            volume,
+                            muted: Some(false),
+                        });
+                        pending_save = Some(Instant::now() + SAVE_DELAY);
+                    }
+
+                    let volume = watchers.mapping().device_volume(volume);
+                    let volume = volume.clamp(0.0, watchers.max_volume());
+
+                    if current_device.is_none() {
+                        continue;
+                    }
+
+                    fade = None;
+
+                    let state = Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        |device| {
+                            Self::set_volume_and_mute(
+                                device,
+                                volume,
+                                Some(false),
+                                max_slew_rate,
+                                &mut slew_target,
+                            )
+                        },
+                    );
+
+                    // The callback ignores our own changes, so whoever was showing the device as muted wouldn't know.
+                    if let Some(state) = state {
+                        watchers.send_volume(Some(state));
+                    }
+                }
+                AudioThreadCommand::SetVolumeUnclamped(volume) => {
+                    if !volume.is_finite() {
+                        eprintln!("ignoring a volume of {volume}");
+                        continue;
+                    }
+
+                    slew_target = None;
+                    fade = None;
+
+                    // The cap is skipped, but the scalar still has to be valid.
+                    let volume = watchers.mapping().device_volume(volume.clamp(0.0, 1.0));
+
+                    if current_device.is_none() {
+                        continue;
+                    }
+
+                    let max_volume = watchers.max_volume();
+
+                    if volume > max_volume {
+                        eprintln!("setting volume to {volume} above the cap of {max_volume}");
+                    }
+
+                    Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        |device| device.set_volume(volume, &LOCAL_VOLUME_CHANGE_GUID),
+                    );
+                }
+                AudioThreadCommand::SetVolumeDb(db) => {
+                    slew_target = None;
+                    fade = None;
+
+                    if db.is_nan() {
+                        eprintln!("ignoring a volume of NaN dB");
+                        continue;
+                    }
+
+                    let max_volume = watchers.max_volume();
+
+                    Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        |device| {
+                            let (min_db, max_db, _) = device.volume_range()?;
+                            let db = db.clamp(min_db, max_db);
+
+                            // There's no way to tell which scalar a dB value maps to without setting it, so this is
+                            // only used to decide whether the exact value is safe to set.
+                            let approximate = db_to_scalar(db, min_db, max_db);
+                            let ramped =
+                                max_slew_rate.is_some() && approximate > device.volume()?;
+                            let below_cap = max_volume >= MAX_NORMALIZED_VOLUME_LEVEL
+                                || db_to_scalar(db + DB_CAP_MARGIN, min_db, max_db) < max_volume;
+
+                            // Setting a value that could be above the cap would briefly go above it.
+                            if ramped || !below_cap {
+                                let volume = approximate.min(max_volume);
+                                return Self::set_volume_limited(
+                                    device,
+                                    volume,
+                                    max_slew_rate,
+                                    &mut slew_target,
+                                );
+                            }
+
+                            device.set_volume_db(db)?;
+
+                            // The margin should cover how far off the approximation is, but the cap wins if it doesn't.
+                            if device.volume()? > max_volume {
+                                device.set_volume(max_volume, &LOCAL_VOLUME_CHANGE_GUID)?;
+                            }
+
+                            Ok(())
+                        },
+                    );
+                }
+                AudioThreadCommand::GetVolumeRange(reply) => {
+                    let range = Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        AudioOutputDevice::volume_range,
+                    );
+
+                    if reply.send(range).is_err() {
+                        eprintln!("volume range was requested, but nobody is waiting for it");
+                    }
+                }
+                AudioThreadCommand::GetVolumeDb(reply) => {
+                    let db = Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        AudioOutputDevice::volume_db,
+                    );
+
+                    if reply.send(db).is_err() {
+                        eprintln!("volume in dB was requested, but nobody is waiting for it");
+                    }
+                }
+                AudioThreadCommand::SetVolumeFractionOfMax(fraction) => {
+                    if !fraction.is_finite() {
+                        eprintln!("ignoring a volume fraction of {fraction}");
+                        continue;
+                    }
+
+                    slew_target = None;
+                    fade = None;
+
+                    let mapping = watchers.mapping();
+                    let max_volume = watchers.max_volume();
+
+                    // The mapping can put 0.0 above the cap, so the result is capped once more.
+                    let volume = mapping
+                        .device_volume(fraction.clamp(0.0, 1.0) * mapping.app_volume(max_volume))
+                        .clamp(0.0, max_volume);
+
+                    // The slider in the frontend sends these, so it's limited just like `SetVolume`.
+                    Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        |device| {
+                            Self::set_volume_limited(
+                                device,
+                                volume,
+                                max_slew_rate,
+                                &mut slew_target,
+                            )
+                        },
+                    );
+                }
+                AudioThreadCommand::FadeVolume {
+                    target,
+                    duration_ms,
+                    curve,
+                } => {
+                    if target.is_nan() {
+                        eprintln!("ignoring a fade to a volume of NaN");
+                        continue;
+                    }
+
+                    slew_target = None;
+                    fade = None;
+
+                    let Some(from) = Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        AudioOutputDevice::volume,
+                    ) else {
+                        continue;
+                    };
+
+                    let target = watchers.mapping().device_volume(target.clamp(0.0, 1.0));
+                    let target = target.clamp(0.0, watchers.max_volume());
+
+                    let now = Instant::now();
+                    let mut duration = Duration::from_millis(duration_ms.into());
+
+                    // A short fade shouldn't be a way around the slew limit, so it's stretched to the time the limit needs,
+                    // even where the curve is at its steepest.
+                    if let Some(rate) = max_slew_rate.filter(|_| target > from) {
+                        let needed = (target - from) / rate * curve.steepest_slope();
+                        duration = duration.max(Duration::from_secs_f32(needed));
+                    }
+
+                    fade = Some(Fade {
+                        from,
+                        target,
+                        curve,
+                        start: now,
+                        duration,
+                        next_step: now,
+                    });
+                }
+                AudioThreadCommand::AdjustVolume(delta) => {
+                    if current_device.is_none() {
+                        continue;
+                    }
+
+                    if delta.is_nan() {
+                        eprintln!("ignoring a volume adjustment of NaN");
+                        continue;
+                    }
+
+                    slew_target = None;
+                    fade = None;
+
+                    let mapping = watchers.mapping();
+                    let max_volume = watchers.max_volume();
+
+                    let state = Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        |device| {
+                            let volume =
+                                (mapping.app_volume(device.volume()?) + delta).clamp(0.0, 1.0);
+                            let volume = mapping.device_volume(volume).clamp(0.0, max_volume);
+
+                            Self::set_volume_limited(
+                                device,
+                                volume,
+                                max_slew_rate,
+                                &mut slew_target,
+                            )?;
+                            device.state()
+                        },
+                    );
+
+                    // Whoever asked for the adjustment doesn't know the new volume, and the callback ignores our own changes.
+                    // While it's being ramped up to, this is where the ramp starts.
+                    if let Some(state) = state {
+                        watchers.send_volume(Some(state));
+                    }
+                }
+                AudioThreadCommand::SetVolumeWithContext { volume, context } => {
+                    if !volume.is_finite() {
+                        eprintln!("ignoring a volume of {volume}");
+                        continue;
+                    }
+
+                    slew_target = None;
+                    fade = None;
+
+                    let volume = watchers.mapping().device_volume(volume.clamp(0.0, 1.0));
+                    let volume = volume.clamp(0.0, watchers.max_volume());
+
+                    Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        |device| device.set_volume(volume, &context),
+                    );
+                }
+                AudioThreadCommand::SetIgnoredVolumeContexts(contexts) => {
+                    ignored_contexts = contexts
+                }
+                AudioThreadCommand::SetMute(muted) => {
+                    let volume = Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        |device| {
+                            device.set_mute(muted)?;
+                            device.volume()
+                        },
+                    );
+
+                    if let Some(volume) = volume.filter(|_| persistence_path.is_some()) {
+                        Self::save_mute(
+                            &mut saved_volume,
+                            &mut pending_save,
+                            muted,
+                            volume,
+                            &watchers,
+                        );
+                    }
+                }
+                AudioThreadCommand::ToggleMute => {
+                    let state = Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        |device| {
+                            let state = device.state()?;
+                            device.set_mute(!state.muted)?;
+
+                            Ok(VolumeState {
+                                muted: !state.muted,
+                                ..state
+                            })
+                        },
+                    );
+
+                    let Some(state) = state else {
+                        continue;
+                    };
+
+                    if persistence_path.is_some() {
+                        Self::save_mute(
+                            &mut saved_volume,
+                            &mut pending_save,
+                            state.muted,
+                            state.volume,
+                            &watchers,
+                        );
+                    }
+
+                    // The callback ignores our own changes, and whoever toggled it doesn't know which way it went.
+                    watchers.send_volume(Some(state));
+                }
+                AudioThreadCommand::SetBalance(balance) => {
+                    channel_slew_targets.clear();
+
+                    if balance.is_nan() {
+                        eprintln!("ignoring a balance of NaN");
+                        continue;
+                    }
+
+                    Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        |device| device.set_balance(balance.clamp(-1.0, 1.0)),
+                    );
+                }
+                AudioThreadCommand::SetFrontRearBalance(balance) => {
+                    channel_slew_targets.clear();
+
+                    if balance.is_nan() {
+                        eprintln!("ignoring a front/rear balance of NaN");
+                        continue;
+                    }
+
+                    let Some(device) = current_device.as_ref() else {
+                        continue;
+                    };
+
+                    let Some(channel_mask) = Self::channel_mask(device, &device_enumerator) else {
+                        eprintln!(
+                            "can't set front/rear balance without knowing the speaker layout"
+                        );
+                        continue;
+                    };
+
+                    Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        |device| {
+                            device.set_front_rear_balance(balance.clamp(-1.0, 1.0), channel_mask)
+                        },
+                    );
+                }
+                AudioThreadCommand::GetFrontRearBalance(reply) => {
+                    let channel_mask = current_device
+                        .as_ref()
+                        .and_then(|device| Self::channel_mask(device, &device_enumerator));
+
+                    let balance = channel_mask.and_then(|channel_mask| {
+                        Self::with_current_device(
+                            &mut current_device,
+                            &mut pending_device_list,
+                            &watchers,
+                            |device| device.front_rear_balance(channel_mask),
+                        )
+                        .flatten()
+                    });
+
+                    if reply.send(balance).is_err() {
+                        eprintln!("front/rear balance was requested, but nobody is waiting for it");
+                    }
+                }
+                AudioThreadCommand::SetChannelVolume { channel, level } => {
+                    if level.is_nan() {
+                        eprintln!("ignoring a volume of NaN for channel {channel}");
+                        continue;
+                    }
+
+                    let level = watchers.mapping().device_volume(level.clamp(0.0, 1.0));
+
+                    // A single channel shouldn't be a way around the cap or the slew limit either.
+                    let level = level.clamp(0.0, watchers.max_volume());
+
+                    Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        |device| {
+                            let raised = device
+                                .channel_levels()?
+                                .get(channel as usize)
+                                .is_some_and(|&current| level > current);
+
+                            if max_slew_rate.is_some() && raised {
+                                if channel_slew_targets.is_empty() {
+                                    next_channel_slew_step = Instant::now();
+                                }
+
+                                channel_slew_targets.insert(channel, level);
+                                return Ok(());
+                            }
+
+                            channel_slew_targets.remove(&channel);
+                            device.set_channel_level(channel, level)
+                        },
+                    );
+                }
+                AudioThreadCommand::ApplyMixerState(mixer_state) => {
+                    if current_device.is_none() {
+                        continue;
+                    }
+
+                    // Like a new volume or balance, the new state replaces whatever was still underway.
+                    fade = None;
+                    channel_slew_targets.clear();
+                    next_channel_slew_step = Instant::now();
+
+                    let mapping = watchers.mapping();
+                    let max_volume = watchers.max_volume();
+                    let volume = mixer_state.volume.clamp(0.0, 1.0);
+
+                    let state = Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        |device| {
+                            // Everything is checked before anything is set, so the device never ends up with a mix
+                            // of the old and the new state.
+                            if let Err(reason) = mixer_state.check(device.channel_count()?) {
+                                eprintln!("ignoring mixer state {mixer_state:?}, since {reason}");
+                                return Ok(None);
+                            }
+
+                            let device_volume =
+                                mapping.device_volume(volume).clamp(0.0, max_volume);
+                            Self::set_volume_limited(
+                                device,
+                                device_volume,
+                                max_slew_rate,
+                                &mut slew_target,
+                            )?;
+
+                            if let Some(balance) = mixer_state.balance {
+                                device.set_balance(balance.clamp(-1.0, 1.0))?;
+                            }
+
+                            if let Some(levels) = &mixer_state.channel_levels {
+                                let current_levels = device.channel_levels()?;
+
+                                for ((channel, &level), &current) in
+                                    (0..).zip(levels).zip(&current_levels)
+                                {
+                                    let level = mapping
+                                        .device_volume(level.clamp(0.0, 1.0))
+                                        .clamp(0.0, max_volume);
+
+                                    if max_slew_rate.is_some() && level > current {
+                                        channel_slew_targets.insert(channel, level);
+                                    } else {
+                                        device.set_channel_level(channel, level)?;
+                                    }
+                                }
+                            }
+
+                            device.set_mute(mixer_state.muted)?;
+                            device.state().map(Some)
+                        },
+                    );
+
+                    let Some(state) = state.flatten() else {
+                        continue;
+                    };
+
+                    if persistence_path.is_some() {
+                        saved_volume = Some(SavedVolume {
+                            volume,
+                            muted: Some(mixer_state.muted),
+                        });
+                        pending_save = Some(Instant::now() + SAVE_DELAY);
+                    }
+
+                    // The callback ignores our own changes, so this is the only time the frontend hears about it.
+                    watchers.send_volume(Some(state));
+                }
+                AudioThreadCommand::GetChannelVolumes(reply) => {
+                    let mapping = watchers.mapping();
+                    let levels = Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        AudioOutputDevice::channel_levels,
+                    )
+                    .map(|levels| {
+                        levels
+                            .into_iter()
+                            .map(|level| mapping.app_volume(level))
+                            .collect()
+                    });
+
+                    if reply.send(levels).is_err() {
+                        eprintln!("channel volumes were requested, but nobody is waiting for them");
+                    }
+                }
+                AudioThreadCommand::AddMirror { source, target } => {
+                    let mirror = match mirrors.iter().position(|m| m.source.device_id == source) {
+                        Some(index) => &mut mirrors[index],
+                        None => {
+                            // SAFETY: `mirror_callback` only sends on an unbounded channel, which never blocks,
+                            // and its argument doesn't hold any endpoint volume objects.
+                            let source_device = watchers.report_audio_error(unsafe {
+                                AudioOutputDevice::acquire(
+                                    source.clone(),
+                                    &device_enumerator,
+                                    Self::mirror_callback,
+                                    (source, command_sender.clone()),
+                                )
+                            });
+
+                            let Some(source_device) = source_device else {
+                                continue;
+                            };
+
+                            mirrors.push(Mirror {
+                                source: source_device,
+                                targets: Vec::new(),
+                            });
+                            mirrors.last_mut().expect("mirror was just added")
+                        }
+                    };
+
+                    if mirror.targets.iter().any(|t| t.device_id == target) {
+                        continue;
+                    }
+
+                    // Targets aren't listened to, since only the source is mirrored.
+                    // SAFETY: The callback does nothing.
+                    let target_device = watchers.report_audio_error(unsafe {
+                        AudioOutputDevice::acquire(target, &device_enumerator, |_, _, _| Ok(()), ())
+                    });
+
+                    let Some(target_device) = target_device else {
+                        mirrors.retain(|m| !m.targets.is_empty());
+                        continue;
+                    };
+
+                    // Bring the new target in line with the source right away.
+                    let synced = mirror.source.volume().and_then(|volume| {
+                        target_device.set_mirrored_volume(volume, watchers.max_volume())
+                    });
+
+                    if let Err(e) = synced {
+                        eprintln!(
+                            "failed to mirror {} to {}: {e}",
+                            mirror.source.name, target_device.name
+                        );
+                        mirrors.retain(|m| !m.targets.is_empty());
+                        continue;
+                    }
+
+                    mirror.targets.push(target_device);
+                }
+                AudioThreadCommand::RemoveMirror { source, target } => {
+                    for mirror in mirrors.iter_mut().filter(|m| m.source.device_id == source) {
+                        mirror.targets.retain(|t| t.device_id != target);
+                    }
+
+                    mirrors.retain(|m| !m.targets.is_empty());
+                }
+                AudioThreadCommand::VolumeChanged {
+                    device_id,
+                    context,
+                    state,
+                    balance,
+                } => {
+                    // The device was replaced while the notification was on its way.
+                    if current_device.as_ref().map(|device| &device.device_id) != Some(&device_id) {
+                        continue;
+                    }
+
+                    if context != MIRROR_VOLUME_CHANGE_GUID {
+                        if change_history.len() == VOLUME_CHANGE_HISTORY_LENGTH {
+                            change_history.pop_front();
+                        }
+
+                        change_history.push_back(ExternalVolumeChange {
+                            context: format!("{context:?}"),
+                            volume: state.volume,
+                            timestamp: unix_millis(),
+                        });
+                    }
+
+                    if ignored_contexts.contains(&context) {
+                        continue;
+                    }
+
+                    watchers.send_volume(Some(state));
+
+                    // Most notifications are plain volume changes, so avoid waking up listeners when the balance stayed the same.
+                    watchers.balance.send_if_modified(|current| {
+                        let modified = *current != balance;
+                        *current = balance;
+                        modified
+                    });
+                }
+                AudioThreadCommand::MirrorSourceChanged { source, volume } => {
+                    let max_volume = watchers.max_volume();
+
+                    // A target that stopped responding is most likely gone, and `DeviceRemoved` might not say so in time.
+                    for mirror in mirrors.iter_mut().filter(|m| m.source.device_id == source) {
+                        mirror.targets.retain(|target| {
+                            target
+                                .set_mirrored_volume(volume, max_volume)
+                                .inspect_err(|e| {
+                                    eprintln!("dropping mirror target {}: {e}", target.name)
+                                })
+                                .is_ok()
+                        });
+                    }
+
+                    mirrors.retain(|m| !m.targets.is_empty());
+                }
+                AudioThreadCommand::Pause => {
+                    paused = true;
+                    pending_default = None;
+                    current_device = None;
+
+                    watchers.send_current_device(None);
+                }
+                AudioThreadCommand::Shutdown => break,
+                AudioThreadCommand::Resume => {
+                    paused = false;
+
+                    // The default device is sent by `AudioMonitor::start`, but a pinned one has to be picked up again here.
+                    if let Some(device_id) = pinned_device.clone() {
+                        Self::switch_device(
+                            &mut current_device,
+                            device_id,
+                            &device_enumerator,
+                            &watchers,
+                            &command_sender,
+                            flow,
+                        );
+                    }
+                }
+                AudioThreadCommand::SetTargetDevice(device_id) => {
+                    pinned_device = Some(device_id.clone());
+                    pending_default = None;
+
+                    if !paused {
+                        Self::switch_device(
+                            &mut current_device,
+                            device_id,
+                            &device_enumerator,
+                            &watchers,
+                            &command_sender,
+                            flow,
+                        );
+                    }
+                }
+                AudioThreadCommand::FollowDefault => {
+                    if pinned_device.take().is_none() || paused {
+                        continue;
+                    }
+
+                    let default_device = watchers
+                        .report_audio_error(get_default_device(&device_enumerator, flow, role))
+                        .map(|device| get_device_id(&device))
+                        .filter(|device_id| !blocked_devices.contains(device_id));
+
+                    match default_device {
+                        Some(device_id) => Self::switch_device(
+                            &mut current_device,
+                            device_id,
+                            &device_enumerator,
+                            &watchers,
+                            &command_sender,
+                            flow,
+                        ),
+                        None => {
+                            current_device = None;
+                            watchers.send_current_device(None);
+                        }
+                    }
+                }
+                AudioThreadCommand::SetMaxVolumeSlewRate(rate) => {
+                    max_slew_rate = rate.filter(|rate| *rate > 0.0);
+
+                    if max_slew_rate.is_none() {
+                        slew_target = None;
+                        channel_slew_targets.clear();
+                    }
+                }
+                AudioThreadCommand::SetVolumeMapping { gain, offset } => {
+                    let mapping = VolumeMapping { gain, offset };
+
+                    if !mapping.is_valid() {
+                        eprintln!("ignoring volume mapping {mapping:?}, since it doesn't stay within 0.0..=1.0");
+                        continue;
+                    }
+
+                    watchers
+                        .limits
+                        .send_modify(|limits| limits.mapping = mapping);
+
+                    // The device volume didn't change, but what it's reported as did.
+                    if let Some(state) = Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        AudioOutputDevice::state,
+                    ) {
+                        watchers.send_volume(Some(state));
+                    }
+                }
+                AudioThreadCommand::SetMaxVolume(max_volume) => {
+                    if max_volume.is_nan() {
+                        eprintln!("ignoring a max volume of NaN");
+                        continue;
+                    }
+
+                    let max_volume = max_volume.clamp(0.0, 1.0);
+
+                    watchers
+                        .limits
+                        .send_modify(|limits| limits.max = max_volume);
+
+                    // Changes that are still underway shouldn't end up above the new cap either.
+                    if let Some((target, _)) = slew_target.as_mut() {
+                        *target = target.min(max_volume);
+                    }
+
+                    for target in channel_slew_targets.values_mut() {
+                        *target = target.min(max_volume);
+                    }
+
+                    if let Some(fade) = fade.as_mut() {
+                        fade.from = fade.from.min(max_volume);
+                        fade.target = fade.target.min(max_volume);
+                    }
+
+                    let capped = Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        |device| {
+                            if device.volume()? <= max_volume {
+                                return Ok(None);
+                            }
+
+                            device.set_volume(max_volume, &LOCAL_VOLUME_CHANGE_GUID)?;
+                            device.state().map(Some)
+                        },
+                    );
+
+                    // The callback ignores our own changes, so nobody would hear about it otherwise.
+                    if let Some(state) = capped.flatten() {
+                        watchers.send_volume(Some(state));
+                    }
+                }
+                AudioThreadCommand::SetBlockedDevices(devices) => blocked_devices = devices,
+                AudioThreadCommand::GetVolumeChangeHistory(reply) => {
+                    let history = change_history.iter().cloned().collect();
+
+                    if reply.send(history).is_err() {
+                        eprintln!(
+                            "volume change history was requested, but nobody is waiting for it"
+                        );
+                    }
+                }
+                AudioThreadCommand::ResendState => {
+                    watchers.send_current_device(current_device.as_ref());
+                }
+                AudioThreadCommand::ConfirmVolume(reply) => {
+                    let volume = Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        AudioOutputDevice::volume,
+                    )
+                    .map(|volume| watchers.mapping().app_volume(volume));
+
+                    if reply.send(volume).is_err() {
+                        eprintln!("volume was confirmed, but nobody is waiting for it anymore");
+                    }
+                }
+                AudioThreadCommand::GetVolumeState(reply) => {
+                    let state = Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        AudioOutputDevice::state,
+                    )
+                    .map(|state| watchers.app_state(state));
+
+                    if reply.send(state).is_err() {
+                        eprintln!("volume state was requested, but nobody is waiting for it");
+                    }
+                }
+                AudioThreadCommand::MuteAllOutputs(reply) => {
+                    let mut muted = 0;
+
+                    // Outputs are muted even from a capture monitor, since that's what the command is for.
+                    for device in get_active_devices(&device_enumerator, DataFlow::Render) {
+                        if set_device_mute(&device, true) == Some(false) {
+                            muted += 1;
+
+                            // The user might have unmuted it in between, which would make it show up twice.
+                            let device_id = get_device_id(&device);
+                            if !muted_devices.contains(&device_id) {
+                                muted_devices.push(device_id);
+                            }
+                        }
+                    }
+
+                    if reply.send(muted).is_err() {
+                        eprintln!("muted all outputs, but nobody is waiting for the result");
+                    }
+                }
+                AudioThreadCommand::UnmuteAllOutputs(reply) => {
+                    // Devices that have disappeared since can't be unmuted, and aren't counted.
+                    let unmuted = muted_devices
+                        .drain(..)
+                        .filter_map(|device_id| {
+                            watchers.report_audio_error(get_device(&device_enumerator, &device_id))
+                        })
+                        .filter(|device| set_device_mute(device, false).is_some())
+                        .count();
+
+                    if reply.send(unmuted).is_err() {
+                        eprintln!("unmuted all outputs, but nobody is waiting for the result");
+                    }
+                }
+                AudioThreadCommand::DeviceNameForId { device_id, reply } => {
+                    if reply
+                        .send(device_name_for_id(&device_enumerator, &device_id))
+                        .is_err()
+                    {
+                        eprintln!("device name was requested, but nobody is waiting for it");
+                    }
+                }
+                AudioThreadCommand::DeviceIdForName { name, reply } => {
+                    if reply
+                        .send(device_id_for_name(&device_enumerator, flow, &name))
+                        .is_err()
+                    {
+                        eprintln!("device ID was requested, but nobody is waiting for it");
+                    }
+                }
+                AudioThreadCommand::GetMixFormat(reply) => {
+                    let mix_format =
+                        Self::current_mm_device(&current_device, &device_enumerator, &watchers)
+                            .and_then(|device| match get_mix_format(&device) {
+                                Ok(mix_format) => Some(mix_format),
+                                Err(e) => {
+                                    eprintln!("failed to read mix format: {e}");
+                                    None
+                                }
+                            });
+
+                    if reply.send(mix_format).is_err() {
+                        eprintln!("mix format was requested, but nobody is waiting for it");
+                    }
+                }
+                AudioThreadCommand::SetSessionVolume { pid, level } => {
+                    if level.is_nan() {
+                        eprintln!("ignoring a session volume of NaN");
+                        continue;
+                    }
+
+                    let Some(device) =
+                        Self::current_mm_device(&current_device, &device_enumerator, &watchers)
+                    else {
+                        continue;
+                    };
+
+                    let set = sessions::set_session_volume(
+                        &device,
+                        |session| session.pid == pid,
+                        level.clamp(0.0, 1.0),
+                    );
+
+                    if set == 0 {
+                        eprintln!("no sessions of process {pid} to set the volume of");
+                    }
+                }
+                AudioThreadCommand::SetSessionVolumeByName {
+                    process_name,
+                    level,
+                } => {
+                    if level.is_nan() {
+                        eprintln!("ignoring a session volume of NaN");
+                        continue;
+                    }
+
+                    let Some(device) =
+                        Self::current_mm_device(&current_device, &device_enumerator, &watchers)
+                    else {
+                        continue;
+                    };
+
+                    let set = sessions::set_session_volume(
+                        &device,
+                        |session| {
+                            session
+                                .process_name
+                                .as_deref()
+                                .is_some_and(|name| name.eq_ignore_ascii_case(&process_name))
+                        },
+                        level.clamp(0.0, 1.0),
+                    );
+
+                    if set == 0 {
+                        eprintln!("no sessions of `{process_name}` to set the volume of");
+                    }
+                }
+                AudioThreadCommand::ListSessions(reply) => {
+                    let sessions =
+                        Self::current_mm_device(&current_device, &device_enumerator, &watchers)
+                            .map(|device| sessions::list_sessions(&device))
+                            .unwrap_or_default();
+
+                    if reply.send(sessions).is_err() {
+                        eprintln!("sessions were requested, but nobody is waiting for them");
+                    }
+                }
+                AudioThreadCommand::SetPersistencePath(path) => {
+                    // A volume that hasn't been saved yet belongs to the previous file.
+                    if let (Some(_), Some(saved), Some(old_path)) = (
+                        pending_save.take(),
+                        saved_volume,
+                        persistence_path.as_deref(),
+                    ) {
+                        save_volume(old_path, saved, &watchers.errors);
+                    }
+
+                    saved_volume = path
+                        .as_deref()
+                        .and_then(|path| load_saved_volume(path, &watchers.errors));
+                    pending_restore = path.is_some();
+                    persistence_path = path;
+                }
+                AudioThreadCommand::SetPeakMetering(enabled) => {
+                    peak_metering = enabled;
+
+                    if !enabled {
+                        watchers.send_peak(0.0);
+                    }
+                }
+                AudioThreadCommand::SetPresetsPath(path) => {
+                    if let Some(path) = path.as_deref() {
+                        presets = load_presets(path, &watchers.errors);
+                    }
+
+                    presets_path = path;
+                }
+                AudioThreadCommand::SavePreset(name) => {
+                    // The watch misses our own changes, so the device is read instead.
+                    let Some(volume) = Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        AudioOutputDevice::volume,
+                    ) else {
+                        eprintln!("can't save preset `{name}` without a device");
+                        continue;
+                    };
+
+                    // `SetVolume` expects the volume from before the mapping back.
+                    presets.insert(name, watchers.mapping().app_volume(volume));
+
+                    if let Some(path) = presets_path.as_deref() {
+                        save_presets(path, &presets, &watchers.errors);
+                    }
+                }
+                AudioThreadCommand::RecallPreset(name) => {
+                    let Some(&volume) = presets.get(&name) else {
+                        eprintln!(
+                            "ignoring request to recall preset `{name}`, since it doesn't exist"
+                        );
+                        continue;
+                    };
+
+                    command_sender
+                        .send(AudioThreadCommand::SetVolume(volume))
+                        .expect("this thread owns the receiver");
+                }
+                AudioThreadCommand::ListPresets(reply) => {
+                    let presets = presets
+                        .iter()
+                        .map(|(name, &volume)| Preset {
+                            name: name.clone(),
+                            volume,
+                        })
+                        .collect();
+
+                    if reply.send(presets).is_err() {
+                        eprintln!("presets were requested, but nobody is waiting for them");
+                    }
+                }
+                AudioThreadCommand::RestoreSaved => {
+                    if persistence_path.is_none() {
+                        eprintln!("ignoring request to restore the saved volume, since there's no persistence path");
+                        continue;
+                    }
+
+                    pending_restore = true;
+                }
+                AudioThreadCommand::RestoreVolume { volume, muted } => {
+                    if !volume.is_finite() {
+                        eprintln!("ignoring a restored volume of {volume}");
+                        continue;
+                    }
+
+                    let volume = watchers.mapping().device_volume(volume.clamp(0.0, 1.0));
+                    let volume = volume.clamp(0.0, watchers.max_volume());
+
+                    if current_device.is_none() {
+                        continue;
+                    }
+
+                    fade = None;
+
+                    let state = Self::with_current_device(
+                        &mut current_device,
+                        &mut pending_device_list,
+                        &watchers,
+                        |device| {
+                            Self::set_volume_and_mute(
+                                device,
+                                volume,
+                                muted,
+                                max_slew_rate,
+                                &mut slew_target,
+                            )
+                        },
+                    );
+
+                    // The callback ignores our own changes, so this is the only time the frontend hears about it.
+                    // While it's being ramped up to, this is where the ramp starts.
+                    if let Some(state) = state {
+                        watchers.send_volume(Some(state));
+                    }
+                }
+                AudioThreadCommand::ListOutputDevices(reply) => {
+                    if reply
+                        .send(list_output_devices(&device_enumerator, flow, role))
+                        .is_err()
+                    {
+                        eprintln!("output devices were requested, but nobody is waiting for them");
+                    }
+                }
+                AudioThreadCommand::GetAllDeviceStates(reply) => {
+                    if reply
+                        .send(get_all_device_states(&device_enumerator, flow))
+                        .is_err()
+                    {
+                        eprintln!("device states were requested, but nobody is waiting for them");
+                    }
+                }
+            }
+        }
+
+        // The last volume shouldn't be lost just because the monitor was dropped before it settled.
+        if let (Some(_), Some(saved), Some(path)) =
+            (pending_save, saved_volume, persistence_path.as_deref())
+        {
+            save_volume(path, saved, &watchers.errors);
+        }
+    }
+
+    /// Schedules saving a new mute state, without touching the saved volume.
+    /// If nothing has been saved yet, the volume of the device is saved along with it, as `device_volume`.
+    fn save_mute(
+        saved_volume: &mut Option<SavedVolume>,
+        pending_save: &mut Option<Instant>,
+        muted: bool,
+        device_volume: f32,
+        watchers: &VolumeWatchers,
+    ) {
+        let volume = saved_volume.map_or_else(
+            || watchers.mapping().app_volume(device_volume),
+            |saved| saved.volume,
+        );
+
+        *saved_volume = Some(SavedVolume {
+            volume,
+            muted: Some(muted),
+        });
+        *pending_save = Some(Instant::now() + SAVE_DELAY);
+    }
+
+    /// Sets the volume of `device`, or ramps up to it instead if a slew limit is set and it would raise the volume.
+    /// Turning it down should always be immediate.
+    fn set_volume_limited(
+        device: &AudioOutputDevice,
+        volume: f32,
+        max_slew_rate: Option<f32>,
+        slew_target: &mut Option<(f32, Instant)>,
+    ) -> windows_core::Result<()> {
+        if max_slew_rate.is_some() && volume > device.volume()? {
+            *slew_target = Some((volume, Instant::now()));
+            return Ok(());
+        }
+
+        *slew_target = None;
+        device.set_volume(volume, &LOCAL_VOLUME_CHANGE_GUID)
+    }
+
+    /// Runs `f` on the current device, or returns `None` if there is none.
+    ///
+    /// A device can be invalidated before `DeviceRemoved` arrives (like when it's unplugged mid-change), so if `f`
+    /// fails, the device is dropped, and the default is looked for again once the device list settles.
+    fn with_current_device<T>(
+        current_device: &mut Option<AudioOutputDevice>,
+        pending_device_list: &mut Option<Instant>,
+        watchers: &VolumeWatchers,
+        f: impl FnOnce(&AudioOutputDevice) -> windows_core::Result<T>,
+    ) -> Option<T> {
+        let device = current_device.as_ref()?;
+
+        match f(device) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                let message = format!("lost audio device {}: {e}", device.name);
+                report_error(&watchers.errors, ErrorKind::DeviceLost, message);
+
+                *current_device = None;
+                *pending_device_list = Some(Instant::now() + DEVICE_LIST_SETTLE_DELAY);
+                watchers.send_current_device(None);
+
+                None
+            }
+        }
+    }
+
+    /// Sets the volume like [`Self::set_volume_limited`] and the mute state along with it, if there is one,
+    /// and reads back the state they left the device in.
+    fn set_volume_and_mute(
+        device: &AudioOutputDevice,
+        volume: f32,
+        muted: Option<bool>,
+        max_slew_rate: Option<f32>,
+        slew_target: &mut Option<(f32, Instant)>,
+    ) -> windows_core::Result<VolumeState> {
+        Self::set_volume_limited(device, volume, max_slew_rate, slew_target)?;
+
+        if let Some(muted) = muted {
+            device.set_mute(muted)?;
+        }
+
+        device.state()
+    }
+
+    fn switch_device(
+        current_device: &mut Option<AudioOutputDevice>,
+        device_id: HSTRING,
+        device_enumerator: &IMMDeviceEnumerator,
+        watchers: &VolumeWatchers,
+        command_sender: &mpsc::Sender<AudioThreadCommand>,
+        flow: DataFlow,
+    ) {
+        // SAFETY: `volume_callback` only sends on an unbounded channel, which never blocks,
+        // and its argument doesn't hold any endpoint volume objects.
+        *current_device = watchers.report_audio_error(unsafe {
+            AudioOutputDevice::acquire(
+                device_id.clone(),
+                device_enumerator,
+                Self::volume_callback,
+                (device_id, command_sender.clone()),
+            )
+        });
+
+        // The previous device (and its capture) is dropped by now, so the captures can't overlap.
+        // Loopback only exists for output devices.
+        #[cfg(feature = "loopback-meter")]
+        if let Some(device) = current_device.as_mut().filter(|_| flow == DataFlow::Render) {
+            device.loopback = Some(loopback::LoopbackCapture::start(
+                device.device_id.clone(),
+                watchers.output_level.clone(),
+            ));
+        }
+
+        watchers.send_current_device(current_device.as_ref());
+    }
+
+    /// Looks up the current device again, for the interfaces that `AudioOutputDevice` doesn't keep around.
+    fn current_mm_device(
+        current_device: &Option<AudioOutputDevice>,
+        device_enumerator: &IMMDeviceEnumerator,
+        watchers: &VolumeWatchers,
+    ) -> Option<IMMDevice> {
+        let device = current_device.as_ref()?;
+        watchers.report_audio_error(get_device(device_enumerator, &device.device_id))
+    }
+
+    fn channel_mask(
+        device: &AudioOutputDevice,
+        device_enumerator: &IMMDeviceEnumerator,
+    ) -> Option<u32> {
+        let device = log_audio_error(get_device(device_enumerator, &device.device_id))?;
+
+        match get_mix_format(&device) {
+            Ok(mix_format) => mix_format.channel_mask,
+            Err(e) => {
+                eprintln!("failed to read mix format: {e}");
+                None
+            }
+        }
+    }
+
+    fn volume_callback(
+        data: &AUDIO_VOLUME_NOTIFICATION_DATA,
+        channel_volumes: &[f32],
+        (device_id, command_sender): &(HSTRING, mpsc::Sender<AudioThreadCommand>),
+    ) -> windows_core::Result<()> {
+        // Filter out volume changes we caused ourselves.
+        if data.guidEventContext == LOCAL_VOLUME_CHANGE_GUID {
+            return Ok(());
+        }
+
+        // Everything else is filtered on the audio thread, since the callback can't wait on any locks.
+        let command = AudioThreadCommand::VolumeChanged {
+            device_id: device_id.clone(),
+            context: data.guidEventContext,
+            state: VolumeState {
+                volume: data.fMasterVolume,
+                muted: data.bMuted.as_bool(),
+            },
+            balance: notification_balance(channel_volumes),
+        };
+
+        if let Err(e) = command_sender.send(command) {
+            eprintln!("failed to send volume notification: {e}");
+        }
+
+        Ok(())
+    }
+
+    fn mirror_callback(
+        data: &AUDIO_VOLUME_NOTIFICATION_DATA,
+        _channel_volumes: &[f32],
+        (source, command_sender): &(HSTRING, mpsc::Sender<AudioThreadCommand>),
+    ) -> windows_core::Result<()> {
+        if data.guidEventContext == MIRROR_VOLUME_CHANGE_GUID {
+            return Ok(());
+        }
+
+        let command = AudioThreadCommand::MirrorSourceChanged {
+            source: source.clone(),
+            volume: data.fMasterVolume,
+        };
+
+        if let Err(e) = command_sender.send(command) {
+            eprintln!("failed to send mirrored volume: {e}");
+        }
+
+        Ok(())
+    }
+}
+
+impl VolumeBackend for AudioMonitor {
+    fn watch(&self) -> watch::Receiver<Option<VolumeState>> {
+        self.volume_watch.clone()
+    }
+
+    fn set_volume(&self, volume: f32) {
+        if let Err(e) = self
+            .command_sender
+            .send(AudioThreadCommand::SetVolume(volume))
+        {
+            eprintln!("failed to send volume to audio thread: {e}");
+        }
+    }
+}
+
+/// Configures an [`AudioMonitor`] before it's created, see [`AudioMonitor::builder`].
+#[derive(Debug, Clone, Copy)]
+pub struct AudioMonitorBuilder {
+    apartment: ComApartment,
+    flow: DataFlow,
+    role: DeviceRole,
+    max_volume: f32,
+}
+
+impl Default for AudioMonitorBuilder {
+    fn default() -> Self {
+        Self {
+            apartment: ComApartment::default(),
+            flow: DataFlow::default(),
+            role: DeviceRole::default(),
+            max_volume: MAX_NORMALIZED_VOLUME_LEVEL,
+        }
+    }
+}
+
+impl AudioMonitorBuilder {
+    /// How COM is initialized on the thread that creates the monitor.
+    pub fn apartment(mut self, apartment: ComApartment) -> Self {
+        self.apartment = apartment;
+        self
+    }
+
+    /// Whether to follow output or input devices.
+    pub fn flow(mut self, flow: DataFlow) -> Self {
+        self.flow = flow;
+        self
+    }
+
+    /// Which of the default devices to follow.
+    pub fn role(mut self, role: DeviceRole) -> Self {
+        self.role = role;
+        self
+    }
+
+    /// The highest volume the monitor sets from the start, like [`AudioThreadCommand::SetMaxVolume`] does later.
+    pub fn max_volume(mut self, max_volume: f32) -> Self {
+        self.max_volume = max_volume;
+        self
+    }
+
+    /// Creates the monitor and starts following the default device.
+    ///
+    /// Unlike [`AudioThreadCommand::SetMaxVolume`], which clamps, a max volume outside 0.0..=1.0 is rejected,
+    /// since it's more likely a mistake than a request for the nearest valid cap.
+    pub fn build(self) -> Result<AudioMonitor, BuildError> {
+        if !(0.0..=MAX_NORMALIZED_VOLUME_LEVEL).contains(&self.max_volume) {
+            return Err(BuildError::InvalidMaxVolume(self.max_volume));
+        }
+
+        Ok(AudioMonitor::with_options(self))
+    }
+}
+
+/// Why [`AudioMonitorBuilder::build`] couldn't create a monitor.
+#[derive(Debug, thiserror::Error)]
+pub enum BuildError {
+    #[error("max volume must be within 0.0..=1.0, but was {0}")]
+    InvalidMaxVolume(f32),
+}
+
+/// Why [`AudioMonitor::set_and_wait`] couldn't confirm the volume.
+#[derive(Debug, thiserror::Error)]
+pub enum ConfirmVolumeError {
+    #[error("there is no device to set the volume of")]
+    NoDevice,
+    #[error("audio thread didn't confirm the volume within {0:?}")]
+    TimedOut(Duration),
+    #[error("audio thread isn't running")]
+    Disconnected,
+}
+
+impl Default for AudioMonitor {
+    fn default() -> Self {
+        Self::new(
+            ComApartment::default(),
+            DataFlow::default(),
+            DeviceRole::default(),
+        )
+    }
+}
+
+impl Drop for AudioMonitor {
+    fn drop(&mut self) {
+        self.stop();
+
+        // The thread has already exited if this fails.
+        if self
+            .command_sender
+            .send(AudioThreadCommand::Shutdown)
+            .is_err()
+        {
+            return;
+        }
+
+        // Waiting makes sure the devices are released and the volume is saved before the monitor is gone.
+        if let Some(thread) = self.audio_thread.take() {
+            if thread.join().is_err() {
+                eprintln!("audio thread panicked while shutting down");
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct AudioOutputDevice {
+    device_id: HSTRING,
+    /// The friendly name, or the ID if the device doesn't have one.
+    name: String,
+    volume_interface: IAudioEndpointVolume,
+    volume_callback_object: IAudioEndpointVolumeCallback,
+    /// `None` if the device doesn't expose a meter, which only disables peak metering.
+    meter: Option<IAudioMeterInformation>,
+    #[cfg(feature = "loopback-meter")]
+    loopback: Option<loopback::LoopbackCapture>,
+}
+
+impl AudioOutputDevice {
+    // SAFETY: The methods in the callback must be non-blocking. The callback should never wait on a synchronization object.
+    // The callback should never call `IAudioEndpointVolume::UnregisterControlChangeNotify`.
+    // The callback should never release the final reference on an `EndpointVolume` API object.
+    pub unsafe fn acquire<CallbackArg>(
+        device_id: HSTRING,
+        device_enumerator: &IMMDeviceEnumerator,
+        callback: VolumeCallbackFn<CallbackArg>,
+        callback_arg: CallbackArg,
+    ) -> Result<Option<Self>, AudioError>
+    where
+        CallbackArg: 'static,
+    {
+        let Some(device) = get_device(device_enumerator, &device_id)? else {
+            return Ok(None);
+        };
+        let name = get_device_name(&device).unwrap_or_else(|| device_id.to_string());
+
+        // SAFETY: `device` is a valid reference, the generic is one of the allowed interfaces,
+        // and we don't pass a pointer in `pactivationparams`, so it can't be invalid.
+        let volume_interface =
+            match unsafe { device.Activate::<IAudioEndpointVolume>(CLSCTX_ALL, None) } {
+                Ok(volume) => volume,
+                Err(e) if e.code() == AUDCLNT_E_DEVICE_INVALIDATED => {
+                    eprintln!("audio device was disconnected: {e}");
+                    return Ok(None);
+                }
+                // Some endpoints (mostly virtual ones) just don't expose a volume control.
+                Err(e) if e.code() == E_NOINTERFACE || e.code() == E_ACCESSDENIED => {
+                    eprintln!("audio device doesn't support volume control: {e}");
+                    return Ok(None);
+                }
+                Err(e) => return Err(AudioError::ActivateVolume(e)),
+            };
+
+        // Everything that deals with channels assumes there's at least one.
+        // SAFETY: `volume_interface` is a valid reference.
+        match unsafe { volume_interface.GetChannelCount() } {
+            Ok(0) => {
+                eprintln!("audio device doesn't support volume control: it has no channels");
+                return Ok(None);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("audio device doesn't support volume control: {e}");
+                return Ok(None);
+            }
+        }
+
+        // SAFETY: `device` is a valid reference, the generic is one of the allowed interfaces,
+        // and we don't pass a pointer in `pactivationparams`, so it can't be invalid.
+        let meter = match unsafe { device.Activate::<IAudioMeterInformation>(CLSCTX_ALL, None) } {
+            Ok(meter) => Some(meter),
+            Err(e) => {
+                eprintln!("audio device doesn't support peak metering: {e}");
+                None
+            }
+        };
+
+        let volume_callback_object: IAudioEndpointVolumeCallback = AudioEndpointVolumeCallback {
+            callback,
+            arg: callback_arg,
+        }
+        .into();
+
+        // Without the callback, nothing would be reported, so the device is treated like it couldn't be activated.
+        // SAFETY: `IAudioEndpointVolumeCallback` is the correct interface and `volume_interface` is a valid reference.
+        match unsafe { volume_interface.RegisterControlChangeNotify(&volume_callback_object) } {
+            Ok(()) => {}
+            Err(e) if e.code() == AUDCLNT_E_DEVICE_INVALIDATED => {
+                eprintln!("audio device was disconnected: {e}");
+                return Ok(None);
+            }
+            Err(e) => return Err(AudioError::RegisterVolumeCallback(e)),
+        }
+
+        Ok(Some(Self {
+            device_id,
+            name,
+            volume_interface,
+            volume_callback_object,
+            meter,
+            #[cfg(feature = "loopback-meter")]
+            loopback: None,
+        }))
+    }
+
+    fn volume(&self) -> windows_core::Result<f32> {
+        // SAFETY: `volume_interface` is a valid reference.
+        unsafe { self.volume_interface.GetMasterVolumeLevelScalar() }
+    }
+
+    fn set_volume(&self, volume: f32, context: &GUID) -> windows_core::Result<()> {
+        // SAFETY: `volume_interface` is a valid reference.
+        unsafe {
+            self.volume_interface
+                .SetMasterVolumeLevelScalar(volume, context)
+        }
+    }
+
+    fn set_volume_db(&self, db: f32) -> windows_core::Result<()> {
+        // SAFETY: `volume_interface` is a valid reference.
+        unsafe {
+            self.volume_interface
+                .SetMasterVolumeLevel(db, &LOCAL_VOLUME_CHANGE_GUID)
+        }
+    }
+
+    fn set_mute(&self, muted: bool) -> windows_core::Result<()> {
+        // SAFETY: `volume_interface` is a valid reference.
+        unsafe {
+            self.volume_interface
+                .SetMute(muted, &LOCAL_VOLUME_CHANGE_GUID)
+        }
+    }
+
+    fn channel_count(&self) -> windows_core::Result<u32> {
+        // SAFETY: `volume_interface` is a valid reference.
+        unsafe { self.volume_interface.GetChannelCount() }
+    }
+
+    /// Reads the peak level of what's playing, or `None` if the device doesn't have a meter or reading it failed.
+    fn peak(&self) -> Option<f32> {
+        // SAFETY: `meter` is a valid reference.
+        match unsafe { self.meter.as_ref()?.GetPeakValue() } {
+            Ok(peak) => Some(peak),
+            Err(e) => {
+                eprintln!("failed to read peak level: {e}");
+                None
+            }
+        }
+    }
+
+    fn set_mirrored_volume(&self, volume: f32, max_volume: f32) -> windows_core::Result<()> {
+        // Mirroring shouldn't be a way around the cap.
+        self.set_volume(volume.clamp(0.0, max_volume), &MIRROR_VOLUME_CHANGE_GUID)
+    }
+
+    /// The `(min_db, max_db, increment_db)` that `SetMasterVolumeLevel` accepts.
+    fn volume_range(&self) -> windows_core::Result<(f32, f32, f32)> {
+        let (mut min_db, mut max_db, mut increment_db) = (0.0, 0.0, 0.0);
+
+        // SAFETY: `self.volume_interface` is a valid reference, and the pointers are to valid locals.
+        unsafe {
+            self.volume_interface
+                .GetVolumeRange(&mut min_db, &mut max_db, &mut increment_db)
+        }?;
+
+        Ok((min_db, max_db, increment_db))
+    }
+
+    fn volume_db(&self) -> windows_core::Result<f32> {
+        // SAFETY: `self.volume_interface` is a valid reference.
+        unsafe { self.volume_interface.GetMasterVolumeLevel() }
+    }
+
+    fn state(&self) -> windows_core::Result<VolumeState> {
+        // SAFETY: `volume_interface` is a valid reference.
+        let muted = unsafe { self.volume_interface.GetMute() }?;
+
+        Ok(VolumeState {
+            volume: self.volume()?,
+            muted: muted.as_bool(),
+        })
+    }
+
+    fn balance(&self) -> windows_core::Result<Option<f32>> {
+        if self.channel_count()? < 2 {
+            return Ok(None);
+        }
+
+        // SAFETY: `self.volume_interface` is a valid reference, and channels 0 and 1 exist.
+        let (left, right) = unsafe {
+            (
+                self.volume_interface.GetChannelVolumeLevelScalar(0)?,
+                self.volume_interface.GetChannelVolumeLevelScalar(1)?,
+            )
+        };
+
+        Ok(Some(balance_from_levels(left, right)))
+    }
+
+    fn set_balance(&self, balance: f32) -> windows_core::Result<()> {
+        let channel_count = self.channel_count()?;
+
+        if channel_count < 2 {
+            eprintln!("can't set balance on a device with {channel_count} channel(s)");
+            return Ok(());
+        }
+
+        let master = self.volume()?;
+
+        // The louder channel stays at the master level, and the other one is attenuated.
+        let left = master * (1.0 - balance.max(0.0));
+        let right = master * (1.0 + balance.min(0.0));
+
+        // SAFETY: `self.volume_interface` is a valid reference, and channels 0 and 1 exist.
+        unsafe {
+            self.volume_interface.SetChannelVolumeLevelScalar(
+                0,
+                left,
+                &LOCAL_VOLUME_CHANGE_GUID,
+            )?;
+            self.volume_interface
+                .SetChannelVolumeLevelScalar(1, right, &LOCAL_VOLUME_CHANGE_GUID)
+        }
+    }
+
+    fn channel_levels(&self) -> windows_core::Result<Vec<f32>> {
+        (0..self.channel_count()?)
+            .map(|channel| {
+                // SAFETY: `self.volume_interface` is a valid reference, and `channel` is below the channel count.
+                unsafe { self.volume_interface.GetChannelVolumeLevelScalar(channel) }
+            })
+            .collect()
+    }
+
+    fn set_channel_level(&self, channel: u32, level: f32) -> windows_core::Result<()> {
+        let channel_count = self.channel_count()?;
+
+        if channel >= channel_count {
+            eprintln!(
+                "can't set volume of channel {channel} on a device with {channel_count} channel(s)"
+            );
+            return Ok(());
+        }
+
+        // SAFETY: `self.volume_interface` is a valid reference, and `channel` is below the channel count.
+        unsafe {
+            self.volume_interface.SetChannelVolumeLevelScalar(
+                channel,
+                level,
+                &LOCAL_VOLUME_CHANGE_GUID,
+            )
+        }
+    }
+
+    /// Pairs the index of each channel with its speaker position.
+    fn channel_speakers(&self, channel_mask: u32) -> windows_core::Result<Vec<(u32, u32)>> {
+        // Channels are assigned to the speakers in the mask from the lowest bit up.
+        Ok((0..self.channel_count()?)
+            .zip(
+                (0..u32::BITS)
+                    .map(|bit| 1 << bit)
+                    .filter(|speaker| channel_mask & speaker != 0),
+            )
+            .collect())
+    }
+
+    fn front_rear_balance(&self, channel_mask: u32) -> windows_core::Result<Option<f32>> {
+        let mut front = None;
+        let mut rear = None;
+
+        for (channel, speaker) in self.channel_speakers(channel_mask)? {
+            let loudest = if speaker & FRONT_SPEAKERS != 0 {
+                &mut front
+            } else if speaker & REAR_SPEAKERS != 0 {
+                &mut rear
+            } else {
+                continue;
+            };
+
+            // SAFETY: `self.volume_interface` is a valid reference, and `channel` is below the channel count.
+            let level = unsafe { self.volume_interface.GetChannelVolumeLevelScalar(channel) }?;
+
+            *loudest = Some(loudest.map_or(level, |loudest: f32| loudest.max(level)));
+        }
+
+        Ok(front
+            .zip(rear)
+            .map(|(front, rear)| balance_from_levels(front, rear)))
+    }
+
+    fn set_front_rear_balance(&self, balance: f32, channel_mask: u32) -> windows_core::Result<()> {
+        let channels = self.channel_speakers(channel_mask)?;
+
+        if !channels
+            .iter()
+            .any(|(_, speaker)| speaker & REAR_SPEAKERS != 0)
+        {
+            eprintln!("can't set front/rear balance on a device without rear speakers");
+            return Ok(());
+        }
+
+        let master = self.volume()?;
+
+        // Like the left/right balance, the louder side stays at the master level, and the other one is attenuated.
+        let front = master * (1.0 - balance.max(0.0));
+        let rear = master * (1.0 + balance.min(0.0));
+
+        for (channel, speaker) in channels {
+            let level = if speaker & FRONT_SPEAKERS != 0 {
+                front
+            } else if speaker & REAR_SPEAKERS != 0 {
+                rear
+            } else {
+                continue;
+            };
+
+            // SAFETY: `self.volume_interface` is a valid reference, and `channel` is below the channel count.
+            unsafe {
+                self.volume_interface.SetChannelVolumeLevelScalar(
+                    channel,
+                    level,
+                    &LOCAL_VOLUME_CHANGE_GUID,
+                )
+            }?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A volume fade started by [`AudioThreadCommand::FadeVolume`], as device volumes.
+#[derive(Debug, Clone, Copy)]
+struct Fade {
+    from: f32,
+    target: f32,
+    curve: FadeCurve,
+    start: Instant,
+    duration: Duration,
+    next_step: Instant,
+}
+
+impl Fade {
+    fn is_done(&self, now: Instant) -> bool {
+        now >= self.start + self.duration
+    }
+
+    /// Interpolates along the curve, and lands exactly on the target once the fade is done.
+    fn volume_at(&self, now: Instant) -> f32 {
+        if self.is_done(now) {
+            return self.target;
+        }
+
+        let progress = (now - self.start).as_secs_f32() / self.duration.as_secs_f32();
+        self.from + (self.target - self.from) * self.curve.ease(progress)
+    }
+}
+
+#[derive(Debug)]
+struct Mirror {
+    source: AudioOutputDevice,
+    targets: Vec<AudioOutputDevice>,
+}
+
+/// Maps the levels of the left and right channels to a balance between -1.0 (left) and 1.0 (right).
+fn balance_from_levels(left: f32, right: f32) -> f32 {
+    let loudest = left.max(right);
+
+    if loudest == 0.0 {
+        return 0.0;
+    }
+
+    (right - left) / loudest
+}
+
+fn notification_balance(channel_volumes: &[f32]) -> Option<f32> {
+    match *channel_volumes {
+        [left, right, ..] => Some(balance_from_levels(left, right)),
+        _ => None,
+    }
+}
+
+/// Reads the volume of every channel in a volume notification.
+///
+/// `afChannelVolumes` is declared as a single-element array, but the notification actually continues
+/// past the end of the struct with `nChannels` elements in total. A reference to the struct only covers
+/// the first one, so the rest have to be read through the pointer the notification was passed as.
+///
+/// # Safety
+///
+/// `data` has to point to a notification that's followed by the rest of its channel volumes,
+/// like the one passed to `OnNotify`, and it can't be mutated until this returns.
+unsafe fn channel_volumes(data: *const AUDIO_VOLUME_NOTIFICATION_DATA) -> Vec<f32> {
+    // SAFETY: The caller guarantees that `data` points to a valid notification.
+    let (channel_count, first_channel) = unsafe {
+        (
+            (*data).nChannels as usize,
+            std::ptr::addr_of!((*data).afChannelVolumes).cast::<f32>(),
+        )
+    };
+
+    // SAFETY: `first_channel` is derived from `data` without going through a reference, so it can reach
+    // the `nChannels` contiguous `f32`s the caller guarantees follow it. They're valid for reads, properly
+    // aligned, and copied out before returning.
+    unsafe { std::slice::from_raw_parts(first_channel, channel_count) }.to_vec()
+}
+
+impl Drop for AudioOutputDevice {
+    fn drop(&mut self) {
+        // SAFETY: `self.volume_interface` is a valid reference and
+        // `self.volume_callback_object` is the same interface originally registered.
+        unsafe {
+            self.volume_interface
+                .UnregisterControlChangeNotify(&self.volume_callback_object)
+        }
+        .expect("all parameters should be valid");
+    }
+}
+
+#[implement(IAudioEndpointVolumeCallback)]
+struct AudioEndpointVolumeCallback<CallbackArg>
+where
+    CallbackArg: 'static,
+{
+    callback: VolumeCallbackFn<CallbackArg>,
+    arg: CallbackArg,
+}
+
+impl<CallbackArg> IAudioEndpointVolumeCallback_Impl
+    for AudioEndpointVolumeCallback_Impl<CallbackArg>
+{
+    fn OnNotify(&self, pnotify: *mut AUDIO_VOLUME_NOTIFICATION_DATA) -> windows_core::Result<()> {
+        // SAFETY: `pnotify` is guaranteed to be a valid pointer to `AUDIO_VOLUME_NOTIFICATION_DATA`,
+        // followed by the rest of its channel volumes.
+        let channel_volumes = unsafe { channel_volumes(pnotify) };
+
+        // SAFETY: As above. The reference only covers the struct itself, which is why the channel volumes
+        // were read through the pointer first.
+        let notification_data = unsafe { &*pnotify };
+        (self.callback)(notification_data, &channel_volumes, &self.arg)
+    }
+}
+
+#[implement(IMMNotificationClient)]
+struct MMNotificationClient {
+    default_device_notifier: mpsc::Sender<AudioThreadCommand>,
+    notifications: broadcast::Sender<DeviceNotification>,
+    /// Only changes of the default device for this flow and role are sent to `default_device_notifier`.
+    flow: EDataFlow,
+    role: ERole,
+    errors: broadcast::Sender<ErrorEvent>,
+}
+
+impl MMNotificationClient {
+    fn notify(&self, notification: DeviceNotification) {
+        // This only fails if nobody is subscribed, and then there's nobody to miss it either.
+        let _ = self.notifications.send(notification);
+    }
+
+    /// Converts a device ID passed to a notification, which is only null for a default device that no longer exists.
+    fn device_id(&self, device_id: &PCWSTR) -> Option<HSTRING> {
+        if device_id.is_null() {
+            return None;
+        }
+
+        // SAFETY: `device_id` isn't null, and notifications are guaranteed to pass null-terminated strings.
+        match unsafe { device_id.to_hstring() } {
+            Ok(device_id) => Some(device_id),
+            Err(e) => {
+                let message =
+                    format!("failed to convert device ID (`{device_id:?}`) to `HSTRING`: {e}");
+                report_error(&self.errors, ErrorKind::Notification, message);
+                None
+            }
+        }
+    }
+}
+
+impl IMMNotificationClient_Impl for MMNotificationClient_Impl {
+    fn OnDeviceStateChanged(
+        &self,
+        pwstrdeviceid: &PCWSTR,
+        dwnewstate: DEVICE_STATE,
+    ) -> windows_core::Result<()> {
+        if let Some(device_id) = self.device_id(pwstrdeviceid) {
+            self.notify(DeviceNotification::StateChanged {
+                device_id,
+                state: dwnewstate,
+            });
+        }
+
+        if let Err(e) = self
+            .default_device_notifier
+            .send(AudioThreadCommand::DevicesChanged)
+        {
+            eprintln!("failed to send notification that device state changed: {e}");
+        }
+
+        Ok(())
+    }
+
+    fn OnDeviceAdded(&self, pwstrdeviceid: &PCWSTR) -> windows_core::Result<()> {
+        if let Some(device_id) = self.device_id(pwstrdeviceid) {
+            self.notify(DeviceNotification::Added(device_id));
+        }
+
+        // Only the audio thread knows whether there's a current device, so it checks for a new default itself.
+        if let Err(e) = self
+            .default_device_notifier
+            .send(AudioThreadCommand::DevicesChanged)
+        {
+            eprintln!("failed to send notification that device was added: {e}");
+        }
+
+        Ok(())
+    }
+
+    fn OnDeviceRemoved(&self, pwstrdeviceid: &PCWSTR) -> windows_core::Result<()> {
+        let Some(removed_device) = self.device_id(pwstrdeviceid) else {
+            return Ok(());
+        };
+
+        self.notify(DeviceNotification::Removed(removed_device.clone()));
+
+        if let Err(e) = self
+            .default_device_notifier
+            .send(AudioThreadCommand::DeviceRemoved(removed_device))
+        {
+            eprintln!("failed to send notification that device was removed: {e}");
+        }
+
+        Ok(())
+    }
+
+    fn OnDefaultDeviceChanged(
+        &self,
+        flow: EDataFlow,
+        role: ERole,
+        pwstrdefaultdeviceid: &PCWSTR,
+    ) -> windows_core::Result<()> {
+        let new_default = self.device_id(pwstrdefaultdeviceid);
+
+        self.notify(DeviceNotification::DefaultChanged {
+            flow,
+            role,
+            device_id: new_default.clone(),
+        });
+
+        if flow != self.flow || role != self.role {
+            return Ok(());
+        }
+
+        let Some(new_default) = new_default else {
+            return Ok(());
+        };
+
+        if let Err(e) = self
+            .default_device_notifier
+            .send(AudioThreadCommand::NewDefault(new_default))
+        {
+            eprintln!("failed to send notification that default device changed: {e}");
+        }
+
+        Ok(())
+    }
+
+    fn OnPropertyValueChanged(
+        &self,
+        pwstrdeviceid: &PCWSTR,
+        key: &PROPERTYKEY,
+    ) -> windows_core::Result<()> {
+        if let Some(device_id) = self.device_id(pwstrdeviceid) {
+            self.notify(DeviceNotification::PropertyChanged {
+                device_id,
+                key: *key,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A notification followed by the rest of its channel volumes, laid out like the ones passed to `OnNotify`.
+    #[repr(C)]
+    struct NotificationBuffer<const N: usize> {
+        data: AUDIO_VOLUME_NOTIFICATION_DATA,
+        rest: [f32; N],
+    }
+
+    fn notification<const N: usize>(volumes: &[f32]) -> NotificationBuffer<N> {
+        assert_eq!(volumes.len(), N + 1);
+
+        NotificationBuffer {
+            data: AUDIO_VOLUME_NOTIFICATION_DATA {
+                guidEventContext: GUID::zeroed(),
+                bMuted: false.into(),
+                fMasterVolume: 0.5,
+                nChannels: volumes.len() as u32,
+                afChannelVolumes: [volumes[0]],
+            },
+            rest: volumes[1..].try_into().expect("length was checked above"),
+        }
+    }
+
+    #[test]
+    fn channel_volumes_reads_past_the_struct() {
+        let buffer = notification::<3>(&[0.1, 0.2, 0.3, 0.4]);
+
+        // SAFETY: The buffer holds the first channel in the struct, and the other three right after it.
+        let volumes = unsafe { channel_volumes(std::ptr::addr_of!(buffer).cast()) };
+
+        assert_eq!(volumes, [0.1, 0.2, 0.3, 0.4]);
+    }
+
+    #[test]
+    fn channel_volumes_reads_a_single_channel() {
+        let buffer = notification::<0>(&[0.7]);
+
+        // SAFETY: The only channel is in the struct itself.
+        let volumes = unsafe { channel_volumes(std::ptr::addr_of!(buffer).cast()) };
+
+        assert_eq!(volumes, [0.7]);
+    }
+
+    #[test]
+    fn notification_balance_needs_two_channels() {
+        assert_eq!(notification_balance(&[0.5]), None);
+        assert_eq!(notification_balance(&[0.5, 1.0, 0.2]), Some(0.5));
+    }
+
+    fn mixer_state(balance: Option<f32>, channel_levels: Option<Vec<f32>>) -> MixerState {
+        MixerState {
+            volume: 0.5,
+            muted: false,
+            balance,
+            channel_levels,
+        }
+    }
+
+    #[test]
+    fn mixer_state_has_to_fit_the_channels() {
+        assert!(mixer_state(None, Some(vec![0.5, 0.5])).check(2).is_ok());
+        assert!(mixer_state(None, Some(vec![0.5, 0.5])).check(6).is_err());
+        assert!(mixer_state(Some(0.5), None).check(2).is_ok());
+        assert!(mixer_state(Some(0.5), None).check(1).is_err());
+    }
+
+    #[test]
+    fn mixer_state_rejects_what_single_commands_ignore() {
+        let nan_volume = MixerState {
+            volume: f32::NAN,
+            ..mixer_state(None, None)
+        };
+
+        assert!(nan_volume.check(2).is_err());
+        assert!(mixer_state(Some(f32::NAN), None).check(2).is_err());
+        assert!(mixer_state(None, Some(vec![0.5, f32::NAN]))
+            .check(2)
+            .is_err());
+        assert!(mixer_state(Some(0.5), Some(vec![0.5, 0.5]))
+            .check(2)
+            .is_err());
+    }
+
+    #[test]
+    fn fade_curves_start_and_end_on_the_ends() {
+        for curve in [
+            FadeCurve::Linear,
+            FadeCurve::EaseInOut,
+            FadeCurve::Exponential,
+        ] {
+            assert_eq!(curve.ease(0.0), 0.0, "{curve:?}");
+            assert!((curve.ease(1.0) - 1.0).abs() < 1e-6, "{curve:?}");
+        }
+    }
+
+    #[test]
+    fn fade_curves_never_outpace_their_steepest_slope() {
+        const STEPS: u16 = 1000;
+
+        for curve in [
+            FadeCurve::Linear,
+            FadeCurve::EaseInOut,
+            FadeCurve::Exponential,
+        ] {
+            for step in 0..STEPS {
+                let (start, end) = (
+                    f32::from(step) / f32::from(STEPS),
+                    f32::from(step + 1) / f32::from(STEPS),
+                );
+                let slope = (curve.ease(end) - curve.ease(start)) * f32::from(STEPS);
+
+                assert!(
+                    slope <= curve.steepest_slope() + 1e-3,
+                    "{curve:?} at {start}"
+                );
+            }
+        }
+    }
+}
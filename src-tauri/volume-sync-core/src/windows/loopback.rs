@@ -0,0 +1,193 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use serde::Serialize;
+use tokio::sync::watch;
+use windows::{
+    core::*,
+    Win32::{
+        Media::Audio::{Endpoints::*, *},
+        System::Com::*,
+    },
+};
+
+use super::{get_device, initialize_com, log_audio_error, ComApartment};
+
+/// How often the capture buffer is drained, which is also roughly how often levels are emitted.
+const POLL_INTERVAL: Duration = Duration::from_millis(30);
+
+/// Requested size of the capture buffer, in 100-nanosecond units.
+const BUFFER_DURATION: i64 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct OutputLevel {
+    pub peak: f32,
+    pub rms: f32,
+}
+
+/// Captures what's being played on a render device, and reports its level.
+///
+/// The capture runs on its own thread, which is stopped and joined when this is dropped.
+#[derive(Debug)]
+pub struct LoopbackCapture {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl LoopbackCapture {
+    pub fn start(device_id: HSTRING, level_watch: watch::Sender<Option<OutputLevel>>) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = std::thread::spawn({
+            let stop = stop.clone();
+
+            move || {
+                if let Err(e) = capture_thread(&device_id, &level_watch, &stop) {
+                    eprintln!("loopback capture stopped: {e}");
+                }
+
+                level_watch.send_replace(None);
+            }
+        });
+
+        Self {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for LoopbackCapture {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                eprintln!("loopback capture thread panicked");
+            }
+        }
+    }
+}
+
+fn capture_thread(
+    device_id: &HSTRING,
+    level_watch: &watch::Sender<Option<OutputLevel>>,
+    stop: &AtomicBool,
+) -> windows_core::Result<()> {
+    // COM objects can't be shared with the audio thread, so this thread gets its own.
+    let _coinitialize_guard = initialize_com(ComApartment::MultiThreaded);
+
+    // SAFETY: We don't pass a pointer in `punkouter`, so it can't be invalid.
+    let device_enumerator: IMMDeviceEnumerator =
+        unsafe { CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL) }?;
+
+    let Some(device) = log_audio_error(get_device(&device_enumerator, device_id)) else {
+        return Ok(());
+    };
+
+    // SAFETY: `device` is a valid reference, the generic is one of the allowed interfaces,
+    // and we don't pass a pointer in `pactivationparams`, so it can't be invalid.
+    let audio_client = unsafe { device.Activate::<IAudioClient>(CLSCTX_ALL, None) }?;
+
+    // SAFETY: `audio_client` is a valid reference.
+    let mix_format = unsafe { audio_client.GetMixFormat() }?;
+
+    // SAFETY: `mix_format` was just returned by `GetMixFormat`, so it's valid.
+    let (channels, bits_per_sample) =
+        unsafe { ((*mix_format).nChannels, (*mix_format).wBitsPerSample) };
+
+    // SAFETY: `audio_client` is a valid reference, and `mix_format` is the device's own format.
+    let initialized = unsafe {
+        audio_client.Initialize(
+            AUDCLNT_SHAREMODE_SHARED,
+            AUDCLNT_STREAMFLAGS_LOOPBACK,
+            BUFFER_DURATION,
+            0,
+            mix_format,
+            None,
+        )
+    };
+
+    // SAFETY: `mix_format` was allocated by `GetMixFormat`, and isn't used after this.
+    unsafe { CoTaskMemFree(Some(mix_format as *const _)) };
+    initialized?;
+
+    // There's nothing to meter, and the buffers wouldn't hold any samples to read.
+    if channels == 0 {
+        eprintln!("can't capture loopback audio from a device without channels");
+        return Ok(());
+    }
+
+    // The shared-mode mix format is always 32-bit float, but it's cheap to make sure.
+    if bits_per_sample != 32 {
+        eprintln!("unsupported loopback sample size: {bits_per_sample} bits");
+        return Ok(());
+    }
+
+    // SAFETY: `audio_client` is a valid reference, and has been initialized.
+    let capture_client = unsafe { audio_client.GetService::<IAudioCaptureClient>() }?;
+
+    // SAFETY: `audio_client` is a valid reference, and has been initialized.
+    unsafe { audio_client.Start() }?;
+
+    while !stop.load(Ordering::Relaxed) {
+        std::thread::sleep(POLL_INTERVAL);
+
+        let mut peak = 0.0f32;
+        let mut sum_of_squares = 0.0f64;
+        let mut sample_count = 0usize;
+
+        // SAFETY: `capture_client` is a valid reference.
+        while unsafe { capture_client.GetNextPacketSize() }? > 0 {
+            let mut data = std::ptr::null_mut();
+            let mut frames = 0;
+            let mut flags = 0;
+
+            // SAFETY: `capture_client` is a valid reference, and all the out-pointers are valid.
+            unsafe { capture_client.GetBuffer(&mut data, &mut frames, &mut flags, None, None) }?;
+
+            let samples = frames as usize * channels as usize;
+
+            // Silent packets don't have meaningful data, but still count towards the average.
+            if flags & AUDCLNT_BUFFERFLAGS_SILENT.0 as u32 == 0 {
+                // SAFETY: The buffer holds `frames` frames of `channels` 32-bit float samples each,
+                // and stays valid until `ReleaseBuffer` is called.
+                let buffer = unsafe { std::slice::from_raw_parts(data as *const f32, samples) };
+
+                for sample in buffer {
+                    peak = peak.max(sample.abs());
+                    sum_of_squares += f64::from(sample * sample);
+                }
+            }
+
+            sample_count += samples;
+
+            // SAFETY: `capture_client` is a valid reference, and `frames` is what `GetBuffer` returned.
+            unsafe { capture_client.ReleaseBuffer(frames) }?;
+        }
+
+        let rms = if sample_count == 0 {
+            0.0
+        } else {
+            (sum_of_squares / sample_count as f64).sqrt() as f32
+        };
+
+        let level = Some(OutputLevel { peak, rms });
+
+        // Avoid waking up listeners while nothing is playing.
+        level_watch.send_if_modified(|current| {
+            let modified = *current != level;
+            *current = level;
+            modified
+        });
+    }
+
+    // SAFETY: `audio_client` is a valid reference, and has been started.
+    unsafe { audio_client.Stop() }
+}
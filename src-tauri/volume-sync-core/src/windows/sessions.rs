@@ -0,0 +1,187 @@
+use serde::Serialize;
+use windows::{
+    core::*,
+    Win32::{
+        Foundation::{CloseHandle, MAX_PATH},
+        Media::Audio::*,
+        System::{
+            Com::*,
+            Threading::{
+                OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32,
+                PROCESS_QUERY_LIMITED_INFORMATION,
+            },
+        },
+    },
+};
+
+use super::LOCAL_VOLUME_CHANGE_GUID;
+
+/// An audio session on the current device, which is usually a single app playing audio.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    pub pid: u32,
+    /// The name of the executable, like `Spotify.exe`. `None` if the process can't be inspected,
+    /// like for the system sounds session.
+    pub process_name: Option<String>,
+    /// Set by the app itself, which most don't bother with.
+    pub display_name: Option<String>,
+    pub volume: f32,
+    pub muted: bool,
+}
+
+/// A session that can be controlled, along with what it was when it was enumerated.
+struct Session {
+    info: SessionInfo,
+    volume_interface: ISimpleAudioVolume,
+}
+
+/// Lists the sessions on `device` that haven't expired.
+pub(super) fn list_sessions(device: &IMMDevice) -> Vec<SessionInfo> {
+    get_sessions(device)
+        .into_iter()
+        .map(|session| session.info)
+        .collect()
+}
+
+/// Sets the volume of every session on `device` that `matches`, and returns how many were set.
+///
+/// A session can expire between being enumerated and having its volume set, and those aren't counted.
+pub(super) fn set_session_volume(
+    device: &IMMDevice,
+    matches: impl Fn(&SessionInfo) -> bool,
+    level: f32,
+) -> usize {
+    get_sessions(device)
+        .into_iter()
+        .filter(|session| matches(&session.info))
+        .filter(|session| {
+            // SAFETY: `volume_interface` is a valid reference.
+            let result = unsafe {
+                session
+                    .volume_interface
+                    .SetMasterVolume(level, &LOCAL_VOLUME_CHANGE_GUID)
+            };
+
+            match result {
+                Ok(()) => true,
+                Err(e) => {
+                    eprintln!(
+                        "failed to set volume of session for process {}: {e}",
+                        session.info.pid
+                    );
+                    false
+                }
+            }
+        })
+        .count()
+}
+
+/// Enumerates the sessions on `device`, skipping expired ones, and ones that disappear while enumerating.
+fn get_sessions(device: &IMMDevice) -> Vec<Session> {
+    // SAFETY: `device` is a valid reference, the generic is one of the allowed interfaces,
+    // and we don't pass a pointer in `pactivationparams`, so it can't be invalid.
+    let manager = match unsafe { device.Activate::<IAudioSessionManager2>(CLSCTX_ALL, None) } {
+        Ok(manager) => manager,
+        Err(e) => {
+            eprintln!("failed to create audio session manager: {e}");
+            return Vec::new();
+        }
+    };
+
+    // SAFETY: `manager` is a valid reference.
+    let sessions = match unsafe { manager.GetSessionEnumerator() } {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            eprintln!("failed to enumerate audio sessions: {e}");
+            return Vec::new();
+        }
+    };
+
+    // SAFETY: `sessions` is a valid reference.
+    let count = unsafe { sessions.GetCount() }.unwrap_or(0);
+
+    (0..count)
+        // SAFETY: `sessions` is a valid reference.
+        .filter_map(|index| unsafe { sessions.GetSession(index) }.ok())
+        .filter_map(|control| get_session(&control))
+        .collect()
+}
+
+fn get_session(control: &IAudioSessionControl) -> Option<Session> {
+    // Expired sessions stick around until their app releases them, but can't play anything anymore.
+    // SAFETY: `control` is a valid reference.
+    if unsafe { control.GetState() }.ok()? == AudioSessionStateExpired {
+        return None;
+    }
+
+    let control2 = control.cast::<IAudioSessionControl2>().ok()?;
+    let volume_interface = control.cast::<ISimpleAudioVolume>().ok()?;
+
+    // SAFETY: `control2` is a valid reference.
+    let pid = unsafe { control2.GetProcessId() }.ok()?;
+
+    // SAFETY: `volume_interface` is a valid reference.
+    let (volume, muted) = unsafe {
+        (
+            volume_interface.GetMasterVolume(),
+            volume_interface.GetMute(),
+        )
+    };
+
+    Some(Session {
+        info: SessionInfo {
+            pid,
+            process_name: process_name(pid),
+            display_name: display_name(control),
+            volume: volume.ok()?,
+            muted: muted.ok()?.as_bool(),
+        },
+        volume_interface,
+    })
+}
+
+fn display_name(control: &IAudioSessionControl) -> Option<String> {
+    // SAFETY: `control` is a valid reference.
+    let name = unsafe { control.GetDisplayName() }.ok()?;
+
+    // SAFETY: `name` was just returned by `GetDisplayName`, so it's a valid null-terminated string.
+    let display_name = unsafe { name.to_string() }.ok();
+
+    // SAFETY: `name` was allocated by `GetDisplayName`, and isn't used after this.
+    unsafe { CoTaskMemFree(Some(name.0 as *const _)) };
+
+    display_name.filter(|name| !name.is_empty())
+}
+
+fn process_name(pid: u32) -> Option<String> {
+    // The system sounds session belongs to the idle process, which can't be opened.
+    if pid == 0 {
+        return None;
+    }
+
+    // SAFETY: Only limited information is requested, which any process can be queried for.
+    let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) }.ok()?;
+
+    let mut buffer = [0u16; MAX_PATH as usize];
+    let mut length = buffer.len() as u32;
+
+    // SAFETY: `process` is a valid handle, and `length` is the size of `buffer`.
+    let result = unsafe {
+        QueryFullProcessImageNameW(
+            process,
+            PROCESS_NAME_WIN32,
+            PWSTR(buffer.as_mut_ptr()),
+            &mut length,
+        )
+    };
+
+    // SAFETY: `process` was opened above, and isn't used after this.
+    if let Err(e) = unsafe { CloseHandle(process) } {
+        eprintln!("failed to close handle of process {pid}: {e}");
+    }
+
+    result.ok()?;
+
+    let path = String::from_utf16_lossy(&buffer[..length as usize]);
+    path.rsplit('\\').next().map(str::to_owned)
+}
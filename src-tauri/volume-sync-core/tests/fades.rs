@@ -0,0 +1,83 @@
+//! Runs against the default output device, and passes without checking anything on machines that don't have one.
+#![cfg(windows)]
+
+use std::time::{Duration, Instant};
+
+use tokio::sync::oneshot;
+use volume_sync_core::{AudioMonitor, AudioThreadCommand, FadeCurve, VolumeState};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const FADE_DURATION: Duration = Duration::from_secs(2);
+
+/// Waits for the monitor to acquire its first device, which only happens once the default has settled.
+/// Returns whether there is one.
+fn wait_for_device(monitor: &AudioMonitor) -> bool {
+    let deadline = Instant::now() + TIMEOUT;
+
+    while Instant::now() < deadline {
+        if monitor.device_id_watch.borrow().is_some() {
+            return true;
+        }
+
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    false
+}
+
+fn volume_state(monitor: &AudioMonitor) -> Option<VolumeState> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+
+    monitor
+        .command_sender
+        .send(AudioThreadCommand::GetVolumeState(reply_tx))
+        .expect("audio thread should be running");
+
+    reply_rx.blocking_recv().expect("audio thread should reply")
+}
+
+#[test]
+fn set_volume_cancels_a_running_fade() {
+    let monitor = AudioMonitor::default();
+
+    if !wait_for_device(&monitor) {
+        eprintln!("skipping, since there is no output device");
+        return;
+    }
+
+    let original = volume_state(&monitor).expect("device should still be there");
+
+    // Fading down, so nothing gets loud if the fade isn't cancelled.
+    monitor
+        .set_and_wait(0.3, TIMEOUT)
+        .expect("volume should be set");
+
+    monitor
+        .command_sender
+        .send(AudioThreadCommand::FadeVolume {
+            target: 0.05,
+            duration_ms: FADE_DURATION.as_millis() as u32,
+            curve: FadeCurve::Linear,
+        })
+        .expect("audio thread should be running");
+
+    std::thread::sleep(FADE_DURATION / 4);
+
+    monitor
+        .command_sender
+        .send(AudioThreadCommand::SetVolume(0.2))
+        .expect("audio thread should be running");
+
+    // Long enough for the fade to have reached its target if it were still running.
+    std::thread::sleep(FADE_DURATION);
+
+    let state = volume_state(&monitor).expect("device should still be there");
+
+    // Put the volume back before asserting, so a failure doesn't leave it changed.
+    monitor
+        .set_and_wait(original.volume, TIMEOUT)
+        .expect("volume should be restored");
+
+    // Drivers can round the volume, so it's only close to what was set.
+    assert!((state.volume - 0.2).abs() < 0.01, "{state:?}");
+}
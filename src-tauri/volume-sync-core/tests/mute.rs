@@ -0,0 +1,91 @@
+//! Runs against the default output device, and passes without checking anything on machines that don't have one.
+#![cfg(windows)]
+
+use std::time::{Duration, Instant};
+
+use tokio::sync::oneshot;
+use volume_sync_core::{AudioMonitor, AudioThreadCommand, VolumeState};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Waits for the monitor to acquire its first device, which only happens once the default has settled.
+/// Returns whether there is one.
+fn wait_for_device(monitor: &AudioMonitor) -> bool {
+    let deadline = Instant::now() + TIMEOUT;
+
+    while Instant::now() < deadline {
+        if monitor.device_id_watch.borrow().is_some() {
+            return true;
+        }
+
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    false
+}
+
+/// Reads the state after every command sent before it has been handled.
+fn volume_state(monitor: &AudioMonitor) -> Option<VolumeState> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+
+    monitor
+        .command_sender
+        .send(AudioThreadCommand::GetVolumeState(reply_tx))
+        .expect("audio thread should be running");
+
+    reply_rx.blocking_recv().expect("audio thread should reply")
+}
+
+fn send(monitor: &AudioMonitor, command: AudioThreadCommand) {
+    monitor
+        .command_sender
+        .send(command)
+        .expect("audio thread should be running");
+}
+
+/// Drivers can round the volume, so it's only close to what was set.
+fn assert_volume(state: VolumeState, volume: f32) {
+    assert!((state.volume - volume).abs() < 0.01, "{state:?}");
+}
+
+#[test]
+fn mute_and_volume_stay_independent() {
+    let monitor = AudioMonitor::default();
+
+    if !wait_for_device(&monitor) {
+        eprintln!("skipping, since there is no output device");
+        return;
+    }
+
+    let original = volume_state(&monitor).expect("device should still be there");
+
+    monitor
+        .set_and_wait(0.3, TIMEOUT)
+        .expect("volume should be set");
+
+    send(&monitor, AudioThreadCommand::SetMute(true));
+    let muted = volume_state(&monitor).expect("device should still be there");
+
+    send(&monitor, AudioThreadCommand::SetVolume(0.4));
+    let still_muted = volume_state(&monitor).expect("device should still be there");
+
+    monitor.set_volume_and_unmute(0.35);
+    let unmuted = volume_state(&monitor).expect("device should still be there");
+
+    // Put everything back before asserting, so a failure doesn't leave it changed.
+    send(&monitor, AudioThreadCommand::SetMute(original.muted));
+    monitor
+        .set_and_wait(original.volume, TIMEOUT)
+        .expect("volume should be restored");
+
+    // Muting keeps the volume.
+    assert!(muted.muted);
+    assert_volume(muted, 0.3);
+
+    // Setting the volume keeps the device muted.
+    assert!(still_muted.muted);
+    assert_volume(still_muted, 0.4);
+
+    assert!(!unmuted.muted);
+    assert_volume(unmuted, 0.35);
+}
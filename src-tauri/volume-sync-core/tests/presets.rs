@@ -0,0 +1,81 @@
+//! Runs against the default output device, and passes without checking anything on machines that don't have one.
+#![cfg(windows)]
+
+use std::time::{Duration, Instant};
+
+use tokio::sync::oneshot;
+use volume_sync_core::{AudioMonitor, AudioThreadCommand, VolumeState};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Waits for the monitor to acquire its first device, which only happens once the default has settled.
+/// Returns whether there is one.
+fn wait_for_device(monitor: &AudioMonitor) -> bool {
+    let deadline = Instant::now() + TIMEOUT;
+
+    while Instant::now() < deadline {
+        if monitor.device_id_watch.borrow().is_some() {
+            return true;
+        }
+
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    false
+}
+
+fn volume_state(monitor: &AudioMonitor) -> Option<VolumeState> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+
+    monitor
+        .command_sender
+        .send(AudioThreadCommand::GetVolumeState(reply_tx))
+        .expect("audio thread should be running");
+
+    reply_rx.blocking_recv().expect("audio thread should reply")
+}
+
+#[test]
+fn saved_preset_includes_our_own_changes() {
+    let monitor = AudioMonitor::default();
+
+    if !wait_for_device(&monitor) {
+        eprintln!("skipping, since there is no output device");
+        return;
+    }
+
+    let original = volume_state(&monitor).expect("device should still be there");
+
+    // The app's own changes never reach `volume_watch`, which is what the preset used to be read from.
+    let volume = monitor
+        .set_and_wait(0.3, TIMEOUT)
+        .expect("volume should be set");
+
+    monitor
+        .command_sender
+        .send(AudioThreadCommand::SavePreset("test".to_string()))
+        .expect("audio thread should be running");
+
+    let (reply_tx, reply_rx) = oneshot::channel();
+
+    monitor
+        .command_sender
+        .send(AudioThreadCommand::ListPresets(reply_tx))
+        .expect("audio thread should be running");
+
+    let presets = reply_rx.blocking_recv().expect("audio thread should reply");
+
+    // Put the volume back before asserting, so a failure doesn't leave it changed.
+    monitor
+        .set_and_wait(original.volume, TIMEOUT)
+        .expect("volume should be restored");
+
+    let preset = presets
+        .iter()
+        .find(|preset| preset.name == "test")
+        .expect("preset should be saved");
+
+    // Drivers can round the volume, so only the one the device ended up at is exact.
+    assert_eq!(preset.volume, volume);
+    assert!((preset.volume - 0.3).abs() < 0.01);
+}
@@ -2,14 +2,33 @@ import React, { useState, useEffect } from "react";
 import { listen, emit } from "@tauri-apps/api/event";
 import "./App.css";
 
+type VolumeEvent =
+    | { state: "no-device" }
+    | {
+          state: "level";
+          level: number;
+          formatted: string;
+          fraction_of_max: number;
+          at_limit: "min" | "max" | null;
+          muted: boolean;
+      };
+
 const App: React.FC = () => {
     const [volume, setVolume] = useState<number | null>(0.2);
+    const [balance, setBalance] = useState<number | null>(null);
 
     useEffect(() => {
-        // Listen for volume changes
-        listen<number | null>('system-volume-changed', (event) => {
-            setVolume(event.payload);
-        });
+        Promise.all([
+            // Listen for volume changes
+            listen<VolumeEvent>('system-volume-changed', (event) => {
+                setVolume(event.payload.state === "level" ? event.payload.level : null);
+            }),
+
+            // Listen for balance changes
+            listen<number | null>('system-balance-changed', (event) => {
+                setBalance(event.payload);
+            }),
+        ]).then(() => emit('frontend-ready'));
     }, []);
 
 
@@ -20,6 +39,33 @@ const App: React.FC = () => {
         emit('web-volume-changed', newVolume);
     };
 
+    const handleBalanceChange = async (newBalance: number) => {
+        setBalance(newBalance);
+
+        // Send the new balance to the backend
+        emit('web-balance-changed', newBalance);
+    };
+
+    function BalanceControl() {
+        if (balance === null) {
+            return null;
+        }
+
+        return (
+            <div className="balance-control">
+                <input
+                    type="range"
+                    min="-1"
+                    max="1"
+                    step="0.01"
+                    value={balance}
+                    onChange={(e) => handleBalanceChange(Number(e.target.value))}
+                />
+                <p>Balance: {Math.round(balance * 100.0)}</p>
+            </div>
+        );
+    }
+
     function VolumeControl() {
         if (volume === null) {
             return <p>No input devices detected.</p>;
@@ -45,6 +91,7 @@ const App: React.FC = () => {
             <header className="App-header">
                 <h1>Volume Sync App</h1>
                 <VolumeControl />
+                <BalanceControl />
             </header>
         </div>
     );